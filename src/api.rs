@@ -0,0 +1,303 @@
+use crate::apk::{Apk, DexFile};
+use crate::callgraph::{method_class_name, method_signature};
+use crate::features::{fnv1a, FNV_OFFSET};
+use dex::method::MethodIdItem;
+use dex::Dex;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Namespaces treated as framework code when no API list is loaded.
+pub const DEFAULT_FRAMEWORK_PREFIXES: [&str; 5] =
+    ["Landroid/", "Ljava/", "Ljavax/", "Lorg/json/", "Lorg/w3c/"];
+
+/// Where the method invoked by a call site lives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CalleeKind {
+    /// Method of the Android or Java framework.
+    Framework,
+    /// Method of a class defined in the apk being analyzed.
+    SameApp,
+    /// Neither defined in the apk nor known to be part of the framework.
+    Unknown,
+}
+
+/// Description of the framework APIs.
+///
+/// The text form has one entry per line. An entry is either a namespace
+/// prefix ending in `/`, such as `Landroid/`, or a method like
+/// `Landroid/app/Activity;->onCreate(Landroid/os/Bundle;)V`, optionally
+/// followed by a tab and the API level that introduced it. The signature may
+/// be left out to match every overload. Lines starting with `#` are comments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameworkApis {
+    prefixes: Vec<String>,
+    methods: HashMap<String, u32>,
+    classes: HashSet<String>,
+    api_level: Option<u32>,
+}
+
+#[derive(Debug)]
+struct ApiListFormatError {
+    line: usize,
+    entry: String,
+}
+
+impl fmt::Display for ApiListFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "Invalid api list entry {:?} at line {}",
+            self.entry, self.line
+        ))
+    }
+}
+
+impl std::error::Error for ApiListFormatError {}
+
+impl Default for FrameworkApis {
+    fn default() -> Self {
+        let mut apis = FrameworkApis::empty();
+        for prefix in DEFAULT_FRAMEWORK_PREFIXES.iter() {
+            apis.add_prefix(prefix);
+        }
+        apis
+    }
+}
+
+impl FrameworkApis {
+    pub fn empty() -> Self {
+        FrameworkApis {
+            prefixes: Vec::new(),
+            methods: HashMap::new(),
+            classes: HashSet::new(),
+            api_level: None,
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        FrameworkApis::read_from(BufReader::new(fs::File::open(path)?))
+    }
+
+    pub fn read_from<R: BufRead>(r: R) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut apis = FrameworkApis::empty();
+        for (i, line) in r.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split('\t');
+            let entry = fields.next().unwrap_or("");
+            let level = fields.next().map(|level| level.trim().parse());
+            let namespace = entry.starts_with('L') && entry.ends_with('/');
+            let method = entry.starts_with('L') && entry.contains(";->");
+            // Only methods carry an API level; namespaces have none
+            match level {
+                None if namespace => apis.add_prefix(entry),
+                None if method => apis.add_method(entry, 1),
+                Some(Ok(level)) if method => apis.add_method(entry, level),
+                _ => {
+                    return Err(Box::new(ApiListFormatError {
+                        line: i + 1,
+                        entry: String::from(line),
+                    }))
+                }
+            }
+        }
+        Ok(apis)
+    }
+
+    pub fn add_prefix(&mut self, prefix: &str) {
+        self.prefixes.push(String::from(prefix));
+    }
+
+    /// Adds a method entry, `Lclass;->name` or `Lclass;->name(sig)ret`,
+    /// introduced in the given API level.
+    pub fn add_method(&mut self, method: &str, api_level: u32) {
+        if let Some(i) = method.find("->") {
+            self.classes.insert(String::from(&method[..i]));
+        }
+        let level = self
+            .methods
+            .entry(String::from(method))
+            .or_insert(api_level);
+        *level = (*level).min(api_level);
+    }
+
+    /// Restricts the method entries to the ones available in `api_level`.
+    pub fn with_api_level(mut self, api_level: u32) -> Self {
+        self.api_level = Some(api_level);
+        self
+    }
+
+    /// Hash of the entries and API level, stable between runs. Databases
+    /// record it since vectors built with different lists never match.
+    pub fn stable_hash(&self) -> u64 {
+        let mut prefixes: Vec<&String> = self.prefixes.iter().collect();
+        prefixes.sort_unstable();
+        prefixes.dedup();
+        let mut methods: Vec<(&String, &u32)> = self.methods.iter().collect();
+        methods.sort_unstable();
+        let h = prefixes.into_iter().fold(FNV_OFFSET, |h, prefix| {
+            fnv1a(fnv1a(h, prefix.as_bytes()), b",")
+        });
+        let h = methods.into_iter().fold(h, |h, (method, level)| {
+            let h = fnv1a(h, method.as_bytes());
+            let h = fnv1a(h, b"=");
+            let h = fnv1a(h, level.to_string().as_bytes());
+            fnv1a(h, b",")
+        });
+        match self.api_level {
+            Some(level) => fnv1a(fnv1a(h, b"@"), level.to_string().as_bytes()),
+            None => h,
+        }
+    }
+
+    fn has_method(&self, method: &str) -> bool {
+        match (self.methods.get(method), self.api_level) {
+            (Some(level), Some(max)) => *level <= max,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    pub fn is_framework_class(&self, class_name: &str) -> bool {
+        self.classes.contains(class_name) || self.prefixes.iter().any(|p| class_name.starts_with(p))
+    }
+
+    /// Whether `Lclass;->name` with the given prototype is a framework method.
+    /// Classes under a framework namespace match any of their methods.
+    pub fn is_framework_method(&self, class_name: &str, name: &str, signature: &str) -> bool {
+        if self.prefixes.iter().any(|p| class_name.starts_with(p)) {
+            return true;
+        }
+        if !self.classes.contains(class_name) {
+            return false;
+        }
+        let method = format!("{}->{}", class_name, name);
+        self.has_method(&method) || self.has_method(&format!("{}{}", method, signature))
+    }
+}
+
+/// Tags the callees of an apk as framework, same-app or unknown methods.
+pub struct ApiClassifier<'a> {
+    apis: &'a FrameworkApis,
+    app_classes: HashSet<String>,
+}
+
+impl<'a> ApiClassifier<'a> {
+    pub fn new(apis: &'a FrameworkApis, apk: &Apk) -> Result<Self, Box<dyn std::error::Error>> {
+        ApiClassifier::from_dex_files(apis, &apk.dex_files()?)
+    }
+
+    pub fn from_dex_files(
+        apis: &'a FrameworkApis,
+        dex_files: &[&DexFile],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut app_classes = HashSet::new();
+        for dex in dex_files {
+            for class in dex.classes() {
+                app_classes.insert(class?.jtype().type_descriptor().to_string());
+            }
+        }
+        Ok(ApiClassifier { apis, app_classes })
+    }
+
+    pub fn from_classes(apis: &'a FrameworkApis, app_classes: HashSet<String>) -> Self {
+        ApiClassifier { apis, app_classes }
+    }
+
+    pub fn is_app_class(&self, class_name: &str) -> bool {
+        self.app_classes.contains(class_name)
+    }
+
+    /// Classes defined in the apk always count as same-app, even when they
+    /// live in a framework namespace, since they are bundled with the app.
+    pub fn classify_name(&self, class_name: &str, name: &str, signature: &str) -> CalleeKind {
+        if self.is_app_class(class_name) {
+            CalleeKind::SameApp
+        } else if self.apis.is_framework_method(class_name, name, signature) {
+            CalleeKind::Framework
+        } else {
+            CalleeKind::Unknown
+        }
+    }
+
+    /// `None` when the method references indices missing from `dex`.
+    pub fn classify(&self, method: &MethodIdItem, dex: &Dex<Vec<u8>>) -> Option<CalleeKind> {
+        let class_name = method_class_name(method, dex)?;
+        if self.is_app_class(&class_name) {
+            return Some(CalleeKind::SameApp);
+        }
+        let name = dex.get_string(method.name_idx()).ok()?.to_string();
+        let signature = method_signature(method, dex)?;
+        Some(self.classify_name(&class_name, &name, &signature))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_prefixes() {
+        let apis = FrameworkApis::default();
+        assert!(apis.is_framework_method("Landroid/app/Activity;", "onCreate", "()V"));
+        assert!(apis.is_framework_method("Lorg/json/JSONObject;", "put", "()V"));
+        assert!(!apis.is_framework_method("Landroidx/core/Foo;", "bar", "()V"));
+        assert!(!apis.is_framework_method("Lorg/apache/http/Foo;", "bar", "()V"));
+    }
+
+    #[test]
+    fn test_read_api_list() -> Result<(), Box<dyn std::error::Error>> {
+        let list = "# api list\n\
+                    Ldalvik/\n\
+                    Lorg/apache/http/HttpClient;->execute\t1\n\
+                    Lcom/android/Foo;->bar(I)V\t21\n";
+        let apis = FrameworkApis::read_from(list.as_bytes())?;
+        assert!(apis.is_framework_method("Ldalvik/system/DexClassLoader;", "<init>", "()V"));
+        assert!(apis.is_framework_method("Lorg/apache/http/HttpClient;", "execute", "()V"));
+        assert!(apis.is_framework_method("Lcom/android/Foo;", "bar", "(I)V"));
+        assert!(!apis.is_framework_method("Lcom/android/Foo;", "bar", "(J)V"));
+        assert!(!apis.is_framework_method("Landroid/app/Activity;", "onCreate", "()V"));
+
+        let apis = apis.with_api_level(19);
+        assert!(!apis.is_framework_method("Lcom/android/Foo;", "bar", "(I)V"));
+        assert!(apis.is_framework_class("Lcom/android/Foo;"));
+        assert_ne!(apis.stable_hash(), FrameworkApis::default().stable_hash());
+        assert_eq!(
+            FrameworkApis::default().stable_hash(),
+            FrameworkApis::default().stable_hash()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_bad_entry() {
+        assert!(FrameworkApis::read_from("Landroid/Foo;->bar\tnope\n".as_bytes()).is_err());
+        assert!(FrameworkApis::read_from("android.app.Activity\n".as_bytes()).is_err());
+        assert!(FrameworkApis::read_from("Ldalvik/\t21\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_classify_name() {
+        let apis = FrameworkApis::default();
+        let mut classes = HashSet::new();
+        classes.insert(String::from("Landroid/support/v4/app/Fragment;"));
+        let classifier = ApiClassifier::from_classes(&apis, classes);
+        assert_eq!(
+            classifier.classify_name("Landroid/support/v4/app/Fragment;", "onCreate", "()V"),
+            CalleeKind::SameApp
+        );
+        assert_eq!(
+            classifier.classify_name("Landroid/app/Activity;", "onCreate", "()V"),
+            CalleeKind::Framework
+        );
+        assert_eq!(
+            classifier.classify_name("Lcom/google/gson/Gson;", "toJson", "()V"),
+            CalleeKind::Unknown
+        );
+    }
+}
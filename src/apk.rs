@@ -1,27 +1,147 @@
+use crate::manifest::Manifest;
+use crate::signature::{self, Signature};
 use dex;
 use dex::{Dex, DexReader};
 use rc_zip::{prelude::*, EntryContents};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::sync::OnceLock;
 use std::{
     fs,
     io::Read,
     path::{Path, PathBuf},
 };
 
+/// Where the bytes of an archive are read from.
+enum ArchiveSource {
+    File(fs::File),
+    Bytes(Vec<u8>),
+}
+
 struct ApkArchive {
     archive: rc_zip::Archive,
-    file: fs::File,
+    source: ArchiveSource,
+}
+
+const DEX_MAGIC: &[u8] = b"dex\n";
+const ZIP_MAGIC: &[u8] = b"PK";
+
+/// Container of the code to analyze.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InputKind {
+    /// A bare dex file.
+    Dex,
+    Apk,
+    /// A zip archive without manifest, such as a jar with `classes.dex`.
+    Jar,
+    /// An Android library bundle. Its dex files are read from the
+    /// `classes.jar` inside it, so only pre-dexed bundles are supported:
+    /// Java class files are not converted to dex and an aar without any dex
+    /// file is rejected.
+    Aar,
+    /// Split APKs generated by bundletool, under `splits/`.
+    Apks,
+    /// Base and config APKs at the root of the archive.
+    Xapk,
+    /// An Android App Bundle, with the dex files of each module under
+    /// `<module>/dex/`. Its protobuf manifests are not parsed.
+    AppBundle,
 }
 
-type DexFile = Dex<Vec<u8>>;
+pub type DexFile = Dex<Vec<u8>>;
 
-#[allow(dead_code)]
+/// A dex file of the input, parsed on first access.
+struct DexEntry {
+    name: String,
+    /// Index in `Apk::archives`, `None` for bare dex files.
+    archive: Option<usize>,
+    /// Index in `Apk::split_names`.
+    split: usize,
+    role: Option<DexRole>,
+    dex: OnceLock<Result<DexFile, DexLoadError>>,
+}
+
+/// An opened APK, or the merged splits of an app. Its dex files are only
+/// read and parsed when first accessed.
 pub struct Apk {
+    /// Empty for inputs read from memory.
     pub path: String,
-    apk_archive: ApkArchive,
-    pub dex_files: Vec<DexFile>,
+    pub kind: InputKind,
+    archives: Vec<ApkArchive>,
+    /// Names of the split APKs or bundle modules, the base first.
+    split_names: Vec<String>,
+    dex_files: Vec<DexEntry>,
+    payloads: Vec<DexEntry>,
+    /// `None` when the APK has no manifest or it cannot be parsed.
+    pub manifest: Option<Manifest>,
+}
+
+/// Role of a dex file in the multidex set loaded by the runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DexRole {
+    /// `classes.dex`.
+    Primary,
+    /// `classesN.dex`, with N from 2.
+    Secondary(usize),
 }
 
+/// Number of the root `classesN.dex` entry `name`, 1 for `classes.dex`.
+fn multidex_number(name: &str) -> Option<usize> {
+    let n = name.strip_prefix("classes")?.strip_suffix(".dex")?;
+    if n.is_empty() {
+        return Some(1);
+    }
+    if n.starts_with('0') || !n.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    n.parse().ok().filter(|n| *n >= 2)
+}
+
+/// Splits the dex entries of an archive into the multidex set in `dex_dir`,
+/// in loading order, and the other dex files. Like the runtime, the set
+/// stops at the first missing `classesN.dex`.
+fn split_dex_entries<'a>(
+    names: impl Iterator<Item = &'a str>,
+    dex_dir: &str,
+) -> (Vec<String>, Vec<String>) {
+    let mut numbered = BTreeMap::new();
+    let mut payloads = Vec::new();
+    for name in names {
+        match name.strip_prefix(dex_dir).and_then(multidex_number) {
+            Some(n) => {
+                numbered.insert(n, name.to_string());
+            }
+            None if name.ends_with(".dex") => payloads.push(name.to_string()),
+            None => {}
+        }
+    }
+    let mut multidex = Vec::new();
+    for (n, name) in numbered {
+        if n == multidex.len() + 1 {
+            multidex.push(name);
+        } else {
+            payloads.push(name);
+        }
+    }
+    payloads.sort();
+    (multidex, payloads)
+}
+
+/// A dex file of the APK that could not be read or parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DexLoadError {
+    pub name: String,
+    pub message: String,
+}
+
+impl fmt::Display for DexLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to load {}: {}", self.name, self.message)
+    }
+}
+
+impl std::error::Error for DexLoadError {}
+
 #[derive(Debug)]
 struct ApkPathIsInvalid {
     file: PathBuf,
@@ -35,6 +155,105 @@ impl fmt::Display for ApkPathIsInvalid {
 
 impl std::error::Error for ApkPathIsInvalid {}
 
+#[derive(Debug)]
+struct UnknownInputFormat;
+
+impl fmt::Display for UnknownInputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("The input is neither a dex file nor a zip archive.")
+    }
+}
+
+impl std::error::Error for UnknownInputFormat {}
+
+/// A jar or aar without any dex file, e.g. one holding only Java class files.
+#[derive(Debug)]
+pub struct NoDexFiles {
+    pub path: String,
+    pub kind: InputKind,
+}
+
+impl fmt::Display for NoDexFiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The {:?} {} contains no dex file; class files are not supported.",
+            self.kind, self.path
+        )
+    }
+}
+
+impl std::error::Error for NoDexFiles {}
+
+/// File name of `path` without its directory and `.apk` extension.
+fn split_name(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.strip_suffix(".apk").unwrap_or(name).to_string()
+}
+
+/// Modules of an app bundle, the base first.
+fn bundle_modules(archive: &ApkArchive) -> Vec<String> {
+    let mut modules: Vec<String> = archive
+        .archive
+        .entries()
+        .iter()
+        .filter_map(|entry| entry.name().split_once('/'))
+        .filter(|(_, path)| path.starts_with("dex/") || path.starts_with("manifest/"))
+        .map(|(module, _)| module.to_string())
+        .collect();
+    modules.sort_by_key(|module| (module != "base", module.clone()));
+    modules.dedup();
+    modules
+}
+
+/// Nested split APKs of an `.apks` or `.xapk` archive, the base first.
+fn nested_apks(archive: &ApkArchive, kind: InputKind) -> Vec<String> {
+    let names = archive.archive.entries().iter().map(|entry| entry.name());
+    let mut apks: Vec<&str> = match kind {
+        InputKind::Apks => {
+            let splits: Vec<&str> = names
+                .clone()
+                .filter(|name| name.starts_with("splits/") && name.ends_with(".apk"))
+                .collect();
+            if splits.is_empty() {
+                // Standalone APKs target different devices: keep a single one
+                let mut standalones: Vec<&str> = names
+                    .filter(|name| name.starts_with("standalones/") && name.ends_with(".apk"))
+                    .collect();
+                standalones.sort_unstable();
+                standalones.truncate(1);
+                standalones
+            } else {
+                splits
+            }
+        }
+        _ => names
+            .filter(|name| !name.contains('/') && name.ends_with(".apk"))
+            .collect(),
+    };
+    sort_splits(&mut apks);
+    apks.into_iter().map(String::from).collect()
+}
+
+/// Sorts split APK paths with the base first, then the other modules and
+/// finally the config splits, such as `config.xxhdpi.apk` in an xapk or
+/// bundletool's `base-arm64_v8a.apk`.
+fn sort_splits(apks: &mut [&str]) {
+    apks.sort_by_key(|name| {
+        let split = split_name(name);
+        let rank = if split == "base" || split == "base-master" {
+            0
+        } else if split.starts_with("config.")
+            || (split.contains('-') && !split.ends_with("-master"))
+        {
+            2
+        } else {
+            1
+        };
+        (rank, split)
+    });
+}
+
 impl Apk {
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
         let s = {
@@ -48,37 +267,354 @@ impl Apk {
                 }
             }
         };
-        let file = fs::File::open(&path)?;
+        let mut file = fs::File::open(&path)?;
+        let mut magic = [0u8; 4];
+        let read = file.read(&mut magic)?;
+        if magic[..read].starts_with(DEX_MAGIC) {
+            return Ok(Self::from_dex_bytes(s, fs::read(&path)?));
+        }
         let archive = file.read_zip()?;
+        Self::from_archive(
+            s,
+            ApkArchive {
+                archive,
+                source: ArchiveSource::File(file),
+            },
+        )
+    }
+
+    /// Opens an APK, jar, aar or dex file held in memory. The kind of input
+    /// is detected from its content.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, Box<dyn std::error::Error>> {
+        if bytes.starts_with(DEX_MAGIC) {
+            Ok(Self::from_dex_bytes(String::new(), bytes))
+        } else if bytes.starts_with(ZIP_MAGIC) {
+            Self::from_archive(String::new(), ApkArchive::from_bytes(bytes)?)
+        } else {
+            Err(Box::new(UnknownInputFormat))
+        }
+    }
+
+    /// Opens the split APKs of an app, the base first, as a single app.
+    pub fn from_split_paths<P: AsRef<Path>>(
+        paths: &[P],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut apk = Apk::new(String::new(), InputKind::Apk);
+        for path in paths {
+            let name = match path.as_ref().file_stem().and_then(|s| s.to_str()) {
+                Some(name) => name.to_string(),
+                None => {
+                    return Err(Box::new(ApkPathIsInvalid {
+                        file: path.as_ref().to_path_buf(),
+                    }))
+                }
+            };
+            let file = fs::File::open(path)?;
+            let archive = file.read_zip()?;
+            apk.add_split(
+                name,
+                ApkArchive {
+                    archive,
+                    source: ArchiveSource::File(file),
+                },
+            );
+        }
+        if let Some(base) = paths.first() {
+            apk.path = base.as_ref().to_string_lossy().into_owned();
+        }
+        apk.manifest = apk.archives.first().and_then(ApkArchive::load_manifest);
+        Ok(apk)
+    }
+
+    fn new(path: String, kind: InputKind) -> Self {
+        Apk {
+            path,
+            kind,
+            archives: Vec::new(),
+            split_names: Vec::new(),
+            dex_files: Vec::new(),
+            payloads: Vec::new(),
+            manifest: None,
+        }
+    }
+
+    fn from_dex_bytes(path: String, bytes: Vec<u8>) -> Self {
+        let name = String::from("classes.dex");
+        let dex = DexReader::from_vec(bytes).map_err(|e| DexLoadError {
+            name: name.clone(),
+            message: e.to_string(),
+        });
+        let mut apk = Apk::new(path, InputKind::Dex);
+        apk.split_names.push(String::from("base"));
+        apk.dex_files.push(DexEntry {
+            name,
+            archive: None,
+            split: 0,
+            role: Some(DexRole::Primary),
+            dex: OnceLock::from(dex),
+        });
+        apk
+    }
+
+    fn from_archive(path: String, archive: ApkArchive) -> Result<Self, Box<dyn std::error::Error>> {
+        let names = || archive.archive.entries().iter().map(|e| e.name());
+        // An APK may also ship a `classes.jar`; its dex files take precedence
+        let kind = if archive.has_entry("classes.jar") && !archive.has_entry("classes.dex") {
+            InputKind::Aar
+        } else if archive.has_entry("AndroidManifest.xml") {
+            InputKind::Apk
+        } else if archive.has_entry("BundleConfig.pb")
+            || archive.has_entry("base/manifest/AndroidManifest.xml")
+        {
+            InputKind::AppBundle
+        } else if archive.has_entry("toc.pb")
+            || names().any(|name| name.starts_with("splits/") && name.ends_with(".apk"))
+        {
+            InputKind::Apks
+        } else if names().any(|name| !name.contains('/') && name.ends_with(".apk")) {
+            InputKind::Xapk
+        } else {
+            InputKind::Jar
+        };
+
+        let mut apk = Apk::new(path, kind);
+        match kind {
+            InputKind::Aar => {
+                apk.manifest = archive.load_manifest();
+                let jar = ApkArchive::from_bytes(archive.read_entry("classes.jar")?)?;
+                apk.add_split(String::from("base"), jar);
+            }
+            InputKind::AppBundle => {
+                let modules = bundle_modules(&archive);
+                apk.archives.push(archive);
+                for module in modules {
+                    apk.add_module(0, module);
+                }
+            }
+            InputKind::Apks | InputKind::Xapk => {
+                for name in nested_apks(&archive, kind) {
+                    let split = ApkArchive::from_bytes(archive.read_entry(&name)?)?;
+                    apk.add_split(split_name(&name), split);
+                }
+                apk.manifest = apk.archives.first().and_then(ApkArchive::load_manifest);
+            }
+            _ => {
+                apk.manifest = archive.load_manifest();
+                apk.add_split(String::from("base"), archive);
+            }
+        }
+        if matches!(kind, InputKind::Jar | InputKind::Aar) && apk.dex_files.is_empty() {
+            return Err(Box::new(NoDexFiles {
+                path: apk.path,
+                kind,
+            }));
+        }
+        Ok(apk)
+    }
+
+    /// Adds the dex files at the root of a split APK or jar.
+    fn add_split(&mut self, name: String, archive: ApkArchive) {
+        self.archives.push(archive);
+        let index = self.archives.len() - 1;
+        self.add_dex_entries(index, name, "", "");
+    }
 
-        let apk_archive = ApkArchive { archive, file };
-        let dex_files_result = apk_archive.load_dex_files();
+    /// Adds the dex files of a module of the app bundle in `archive`.
+    fn add_module(&mut self, archive: usize, module: String) {
+        let prefix = format!("{}/", module);
+        self.add_dex_entries(archive, module, &prefix, "dex/");
+    }
+
+    fn add_dex_entries(&mut self, archive: usize, split_name: String, prefix: &str, dex_dir: &str) {
+        let names = self.archives[archive]
+            .archive
+            .entries()
+            .iter()
+            .filter_map(|entry| entry.name().strip_prefix(prefix));
+        let (multidex, payloads) = split_dex_entries(names, dex_dir);
+        let split = self.split_names.len();
+        self.split_names.push(split_name);
+        let entry = |name: String, role| DexEntry {
+            name: format!("{}{}", prefix, name),
+            archive: Some(archive),
+            split,
+            role,
+            dex: OnceLock::new(),
+        };
+        for (i, name) in multidex.into_iter().enumerate() {
+            let role = match i {
+                0 => DexRole::Primary,
+                i => DexRole::Secondary(i + 1),
+            };
+            self.dex_files.push(entry(name, Some(role)));
+        }
+        self.payloads
+            .extend(payloads.into_iter().map(|name| entry(name, None)));
+    }
+
+    fn load<'a>(&'a self, entry: &'a DexEntry) -> Result<&'a DexFile, DexLoadError> {
+        let dex = entry.dex.get_or_init(|| {
+            let dex = match entry.archive {
+                Some(archive) => self.archives[archive].load_dex_file(&entry.name),
+                None => Err(Box::new(DexFileIsNotFileError {
+                    file: entry.name.clone(),
+                }) as Box<dyn std::error::Error>),
+            };
+            dex.map_err(|e| DexLoadError {
+                name: entry.name.clone(),
+                message: e.to_string(),
+            })
+        });
+        dex.as_ref().map_err(Clone::clone)
+    }
+
+    /// Names of the split APKs or bundle modules, the base first. Single
+    /// APKs have a `base` split.
+    pub fn split_names(&self) -> &[String] {
+        &self.split_names
+    }
+
+    /// Entry names of the multidex sets of every split: `classes.dex`,
+    /// `classes2.dex`, ...
+    pub fn dex_names(&self) -> Vec<&str> {
+        self.dex_files
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect()
+    }
+
+    pub fn dex_count(&self) -> usize {
+        self.dex_files.len()
+    }
+
+    /// Role of the dex file in the multidex set of its split.
+    pub fn dex_role(&self, index: usize) -> DexRole {
+        self.dex_files[index].role.unwrap_or(DexRole::Primary)
+    }
 
-        let mut dex_files: Vec<DexFile> = Vec::new();
-        for dex in dex_files_result {
-            match dex {
-                Ok(dex) => dex_files.push(dex),
-                Err(e) => return Err(e),
+    /// Name of the split that contains the dex file at `index`.
+    pub fn dex_split(&self, index: usize) -> &str {
+        &self.split_names[self.dex_files[index].split]
+    }
+
+    /// The dex file at `index` in `dex_names`, parsed on first access.
+    pub fn dex(&self, index: usize) -> Result<&DexFile, DexLoadError> {
+        self.load(&self.dex_files[index])
+    }
+
+    /// Split defining each class, by type descriptor. A class defined in
+    /// several splits is attributed to the first one.
+    pub fn class_splits(&self) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+        let mut splits = HashMap::new();
+        for i in 0..self.dex_count() {
+            for class in self.dex(i)?.classes() {
+                let name = class?.jtype().type_descriptor().to_string();
+                splits
+                    .entry(name)
+                    .or_insert_with(|| self.dex_split(i).to_string());
             }
         }
+        Ok(splits)
+    }
 
-        Ok(Apk {
-            path: s,
-            apk_archive: apk_archive,
-            dex_files: dex_files,
-        })
+    /// Every dex file, or the error of the first one that cannot be loaded.
+    pub fn dex_files(&self) -> Result<Vec<&DexFile>, DexLoadError> {
+        (0..self.dex_count()).map(|i| self.dex(i)).collect()
+    }
+
+    /// The dex files that can be loaded, with their index in `dex_names`,
+    /// and the errors of the others.
+    pub fn dex_files_partial(&self) -> (Vec<(usize, &DexFile)>, Vec<DexLoadError>) {
+        let mut loaded = Vec::new();
+        let mut errors = Vec::new();
+        for i in 0..self.dex_count() {
+            match self.dex(i) {
+                Ok(dex) => loaded.push((i, dex)),
+                Err(e) => errors.push(e),
+            }
+        }
+        (loaded, errors)
+    }
+
+    /// Dex files that the runtime does not load by itself, e.g. in `assets/`
+    /// or `res/raw/`. Packers and dynamic code loading use them.
+    pub fn payload_names(&self) -> Vec<&str> {
+        self.payloads
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect()
+    }
+
+    /// The dex file at `index` in `payload_names`, parsed on first access.
+    pub fn payload(&self, index: usize) -> Result<&DexFile, DexLoadError> {
+        self.load(&self.payloads[index])
+    }
+
+    /// Signatures of the APK, or of the base split, and the v4 signature of
+    /// the `<path>.idsig` file next to it. Signatures are not verified.
+    pub fn signatures(&self) -> Result<Vec<Signature>, Box<dyn std::error::Error>> {
+        let mut signatures = match (self.kind, self.archives.first()) {
+            (InputKind::Aar, _) | (_, None) => Vec::new(),
+            (_, Some(archive)) => archive.signatures()?,
+        };
+        let idsig = format!("{}.idsig", self.path);
+        if !self.path.is_empty() && Path::new(&idsig).is_file() {
+            signatures.push(signature::parse_idsig(&fs::read(idsig)?)?);
+        }
+        Ok(signatures)
     }
 }
 
 impl ApkArchive {
-    fn load_dex_files(&self) -> Vec<Result<DexFile, Box<dyn std::error::Error>>> {
-        let mut dex_files: Vec<Result<DexFile, Box<dyn std::error::Error>>> = Vec::new();
-        for name in self.archive.entries() {
-            if name.name().ends_with(".dex") {
-                dex_files.push(self.load_dex_file(name.name()))
+    fn from_bytes(bytes: Vec<u8>) -> Result<Self, Box<dyn std::error::Error>> {
+        let archive = bytes.read_zip()?;
+        Ok(ApkArchive {
+            archive,
+            source: ArchiveSource::Bytes(bytes),
+        })
+    }
+
+    fn has_entry(&self, name: &str) -> bool {
+        self.archive.by_name(name).is_some()
+    }
+
+    fn read_entry(&self, name: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut bytearray: Vec<u8> = Vec::new();
+        read_file_contents(&self, name, &mut bytearray)?;
+        Ok(bytearray)
+    }
+
+    fn load_manifest(&self) -> Option<Manifest> {
+        if !self.has_entry("AndroidManifest.xml") {
+            return None;
+        }
+        let bytearray = self.read_entry("AndroidManifest.xml").ok()?;
+        Manifest::from_axml(&bytearray).ok()
+    }
+
+    /// v1 signatures, then those of the APK Signing Block.
+    fn signatures(&self) -> Result<Vec<Signature>, Box<dyn std::error::Error>> {
+        let mut signatures = Vec::new();
+        for entry in self.archive.entries() {
+            if signature::is_v1_signature_entry(entry.name()) {
+                signatures.push(Signature {
+                    scheme: signature::SignatureScheme::V1,
+                    certificates: signature::parse_pkcs7(&self.read_entry(entry.name())?)?,
+                });
+            }
+        }
+        let block = match &self.source {
+            ArchiveSource::File(file) => {
+                signature::read_signing_block(file, file.metadata()?.len())?
+            }
+            ArchiveSource::Bytes(bytes) => {
+                signature::read_signing_block(&&bytes[..], bytes.len() as u64)?
             }
+        };
+        if let Some(block) = block {
+            signatures.extend(signature::parse_signing_block(&block)?);
         }
-        dex_files
+        Ok(signatures)
     }
 
     fn load_dex_file(&self, dexname: &str) -> Result<DexFile, Box<dyn std::error::Error>> {
@@ -95,10 +631,16 @@ fn read_file_contents(
     buf: &mut Vec<u8>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     if let EntryContents::File(f) = apk.archive.by_name(filename).unwrap().contents() {
-        let mut r = f
-            .entry
-            .reader(|offset| positioned_io::Cursor::new_pos(&apk.file, offset));
-        r.read_to_end(buf)?;
+        match &apk.source {
+            ArchiveSource::File(file) => f
+                .entry
+                .reader(|offset| positioned_io::Cursor::new_pos(file, offset))
+                .read_to_end(buf)?,
+            ArchiveSource::Bytes(bytes) => f
+                .entry
+                .reader(|offset| positioned_io::Cursor::new_pos(&bytes[..], offset))
+                .read_to_end(buf)?,
+        };
         Ok(())
     } else {
         Err(Box::new(DexFileIsNotFileError {
@@ -138,10 +680,111 @@ mod tests {
     fn get_dex() -> Result<(), Box<dyn std::error::Error>> {
         let apk = Apk::from_path("resources/test01.apk")?;
         let dex = DexReader::from_file("resources/testapk/classes.dex")?;
+        assert_eq!(apk.dex(0)?.header().checksum(), dex.header().checksum());
+        Ok(())
+    }
+
+    #[test]
+    fn from_dex() -> Result<(), Box<dyn std::error::Error>> {
+        let apk = Apk::from_path("resources/classes.dex")?;
+        assert_eq!(apk.kind, InputKind::Dex);
+        assert_eq!(apk.dex_names(), &["classes.dex"]);
+        let bytes = fs::read("resources/classes.dex")?;
+        let from_bytes = Apk::from_bytes(bytes)?;
         assert_eq!(
-            apk.dex_files[0].header().checksum(),
-            dex.header().checksum()
+            apk.dex(0)?.header().checksum(),
+            from_bytes.dex(0)?.header().checksum()
         );
         Ok(())
     }
+
+    #[test]
+    fn test_unknown_input() {
+        assert!(Apk::from_bytes(b"\x7fELF".to_vec()).is_err());
+        assert!(Apk::from_bytes(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_sort_splits() {
+        let mut apks = [
+            "splits/base-arm64_v8a.apk",
+            "splits/feature-xxhdpi.apk",
+            "splits/base-xxhdpi.apk",
+            "splits/feature-master.apk",
+            "splits/base-master.apk",
+        ];
+        sort_splits(&mut apks);
+        assert_eq!(
+            apks,
+            [
+                "splits/base-master.apk",
+                "splits/feature-master.apk",
+                "splits/base-arm64_v8a.apk",
+                "splits/base-xxhdpi.apk",
+                "splits/feature-xxhdpi.apk",
+            ]
+        );
+
+        let mut apks = [
+            "config.arm64_v8a.apk",
+            "com.example.app.apk",
+            "config.en.apk",
+        ];
+        sort_splits(&mut apks);
+        assert_eq!(apks[0], "com.example.app.apk");
+    }
+
+    #[test]
+    fn test_jar_without_dex() {
+        // An empty zip: only the end of central directory record.
+        let mut zip = b"PK\x05\x06".to_vec();
+        zip.resize(22, 0);
+        let err = Apk::from_bytes(zip).err().unwrap();
+        assert!(err.downcast_ref::<NoDexFiles>().is_some());
+    }
+
+    #[test]
+    fn test_split_dex_entries() {
+        let names = [
+            "classes3.dex",
+            "assets/payload.dex",
+            "classes.dex",
+            "lib/classes2.dex",
+            "classes2.dex",
+            "classes5.dex",
+            "classes02.dex",
+            "resources.arsc",
+        ];
+        let (multidex, payloads) = split_dex_entries(names.iter().copied(), "");
+        assert_eq!(
+            multidex,
+            vec!["classes.dex", "classes2.dex", "classes3.dex"]
+        );
+        assert_eq!(
+            payloads,
+            vec![
+                "assets/payload.dex",
+                "classes02.dex",
+                "classes5.dex",
+                "lib/classes2.dex"
+            ]
+        );
+        assert_eq!(multidex_number("classes+2.dex"), None);
+        assert_eq!(multidex_number("classes1.dex"), None);
+    }
+
+    #[test]
+    fn test_bundle_dex_entries() {
+        let names = [
+            "dex/classes2.dex",
+            "dex/classes.dex",
+            "root/assets/x.dex",
+            "manifest/AndroidManifest.xml",
+        ];
+        let (multidex, payloads) = split_dex_entries(names.iter().copied(), "dex/");
+        assert_eq!(multidex, vec!["dex/classes.dex", "dex/classes2.dex"]);
+        assert_eq!(payloads, vec!["root/assets/x.dex"]);
+        assert_eq!(split_name("splits/base-master.apk"), "base-master");
+        assert_eq!(split_name("config.arm64_v8a.apk"), "config.arm64_v8a");
+    }
 }
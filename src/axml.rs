@@ -0,0 +1,433 @@
+use std::fmt;
+
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_XML_TYPE: u16 = 0x0003;
+const RES_XML_START_ELEMENT_TYPE: u16 = 0x0102;
+const RES_XML_END_ELEMENT_TYPE: u16 = 0x0103;
+const RES_XML_RESOURCE_MAP_TYPE: u16 = 0x0180;
+
+const UTF8_FLAG: u32 = 1 << 8;
+const NO_INDEX: u32 = 0xffff_ffff;
+
+const TYPE_REFERENCE: u8 = 0x01;
+const TYPE_STRING: u8 = 0x03;
+const TYPE_INT_DEC: u8 = 0x10;
+const TYPE_INT_HEX: u8 = 0x11;
+const TYPE_INT_BOOLEAN: u8 = 0x12;
+
+/// Names of the framework attributes used by manifests, by resource id.
+/// Obfuscators often rename or empty the attribute names of the string pool,
+/// but the platform only looks at the ids.
+const ATTRIBUTE_NAMES: [(u32, &str); 14] = [
+    (0x0101_0001, "label"),
+    (0x0101_0003, "name"),
+    (0x0101_0006, "permission"),
+    (0x0101_000e, "enabled"),
+    (0x0101_0010, "exported"),
+    (0x0101_0018, "authorities"),
+    (0x0101_0026, "mimeType"),
+    (0x0101_0027, "scheme"),
+    (0x0101_0028, "host"),
+    (0x0101_0202, "targetActivity"),
+    (0x0101_020c, "minSdkVersion"),
+    (0x0101_021b, "versionCode"),
+    (0x0101_021c, "versionName"),
+    (0x0101_0270, "targetSdkVersion"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeValue {
+    String(String),
+    Int(i64),
+    Bool(bool),
+    /// Resource id, e.g. of a string in `resources.arsc`.
+    Reference(u32),
+    Other {
+        data_type: u8,
+        data: u32,
+    },
+}
+
+impl fmt::Display for AttributeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttributeValue::String(s) => write!(f, "{}", s),
+            AttributeValue::Int(i) => write!(f, "{}", i),
+            AttributeValue::Bool(b) => write!(f, "{}", b),
+            AttributeValue::Reference(id) => write!(f, "@{:#010x}", id),
+            AttributeValue::Other { data_type, data } => {
+                write!(f, "({:#04x}){:#010x}", data_type, data)
+            }
+        }
+    }
+}
+
+/// Attribute of an element, named without its namespace prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attribute {
+    pub name: String,
+    pub resource_id: Option<u32>,
+    pub value: AttributeValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<Attribute>,
+    pub children: Vec<Element>,
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&AttributeValue> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.name == name)
+            .map(|attribute| &attribute.value)
+    }
+
+    /// Value of the attribute `name` converted to a string.
+    pub fn string_attribute(&self, name: &str) -> Option<String> {
+        self.attribute(name).map(|value| value.to_string())
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AxmlError {
+    /// A chunk or a field at `offset` goes past the end of the file.
+    Truncated {
+        offset: usize,
+    },
+    /// Unexpected chunk type at `offset`.
+    InvalidChunk {
+        offset: usize,
+        chunk_type: u16,
+    },
+    /// End of an element that was not started.
+    Unbalanced {
+        offset: usize,
+    },
+    NoRoot,
+}
+
+impl fmt::Display for AxmlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AxmlError::Truncated { offset } => write!(f, "Truncated binary XML at {:#x}", offset),
+            AxmlError::InvalidChunk { offset, chunk_type } => {
+                write!(f, "Invalid chunk type {:#06x} at {:#x}", chunk_type, offset)
+            }
+            AxmlError::Unbalanced { offset } => {
+                write!(f, "End of an element that was not started at {:#x}", offset)
+            }
+            AxmlError::NoRoot => write!(f, "The binary XML has no root element"),
+        }
+    }
+}
+
+impl std::error::Error for AxmlError {}
+
+fn read_u8(bytes: &[u8], offset: usize) -> Result<u8, AxmlError> {
+    bytes
+        .get(offset)
+        .copied()
+        .ok_or(AxmlError::Truncated { offset })
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, AxmlError> {
+    match bytes.get(offset..offset + 2) {
+        Some(b) => Ok(u16::from_le_bytes([b[0], b[1]])),
+        None => Err(AxmlError::Truncated { offset }),
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, AxmlError> {
+    match bytes.get(offset..offset + 4) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(AxmlError::Truncated { offset }),
+    }
+}
+
+fn read_string(pool: &[u8], offset: usize, utf8: bool) -> Result<String, AxmlError> {
+    if utf8 {
+        // Length in UTF-16 code units, then in bytes, each on 1 or 2 bytes
+        let mut pos = offset;
+        pos += if read_u8(pool, pos)? & 0x80 != 0 {
+            2
+        } else {
+            1
+        };
+        let mut len = read_u8(pool, pos)? as usize;
+        pos += 1;
+        if len & 0x80 != 0 {
+            len = ((len & 0x7f) << 8) | read_u8(pool, pos)? as usize;
+            pos += 1;
+        }
+        let bytes = pool
+            .get(pos..pos + len)
+            .ok_or(AxmlError::Truncated { offset: pos })?;
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    } else {
+        let mut pos = offset;
+        let mut len = read_u16(pool, pos)? as usize;
+        pos += 2;
+        if len & 0x8000 != 0 {
+            len = ((len & 0x7fff) << 16) | read_u16(pool, pos)? as usize;
+            pos += 2;
+        }
+        let units = (0..len)
+            .map(|i| read_u16(pool, pos + 2 * i))
+            .collect::<Result<Vec<u16>, _>>()?;
+        Ok(String::from_utf16_lossy(&units))
+    }
+}
+
+/// Strings of a string pool chunk. Strings that cannot be read are left
+/// empty, as the platform only fails when they are used.
+fn string_pool(chunk: &[u8]) -> Result<Vec<String>, AxmlError> {
+    let header_size = read_u16(chunk, 2)? as usize;
+    let count = read_u32(chunk, 8)? as usize;
+    let flags = read_u32(chunk, 16)?;
+    let strings_start = read_u32(chunk, 20)? as usize;
+    let mut strings = Vec::with_capacity(count.min(chunk.len() / 4));
+    for i in 0..count {
+        let offset = read_u32(chunk, header_size + 4 * i)? as usize;
+        let string = read_string(chunk, strings_start + offset, flags & UTF8_FLAG != 0);
+        strings.push(string.unwrap_or_default());
+    }
+    Ok(strings)
+}
+
+struct Parser {
+    strings: Vec<String>,
+    resource_ids: Vec<u32>,
+}
+
+impl Parser {
+    fn string(&self, idx: u32) -> Option<String> {
+        if idx == NO_INDEX {
+            return None;
+        }
+        self.strings.get(idx as usize).cloned()
+    }
+
+    fn start_element(&self, chunk: &[u8]) -> Result<Element, AxmlError> {
+        let ext = read_u16(chunk, 2)? as usize;
+        let name = self.string(read_u32(chunk, ext + 4)?).unwrap_or_default();
+        let attribute_start = read_u16(chunk, ext + 8)? as usize;
+        let attribute_size = read_u16(chunk, ext + 10)? as usize;
+        let attribute_count = read_u16(chunk, ext + 12)? as usize;
+        let mut attributes = Vec::with_capacity(attribute_count);
+        for i in 0..attribute_count {
+            let at = ext + attribute_start + i * attribute_size;
+            let name_idx = read_u32(chunk, at + 4)?;
+            let raw = read_u32(chunk, at + 8)?;
+            let data_type = read_u8(chunk, at + 15)?;
+            let data = read_u32(chunk, at + 16)?;
+
+            let resource_id = self.resource_ids.get(name_idx as usize).copied();
+            let known = ATTRIBUTE_NAMES
+                .iter()
+                .find(|(id, _)| Some(*id) == resource_id)
+                .map(|(_, name)| name.to_string());
+            let name = known.or_else(|| self.string(name_idx)).unwrap_or_default();
+            let value = match data_type {
+                TYPE_STRING => AttributeValue::String(self.string(data).unwrap_or_default()),
+                TYPE_REFERENCE => AttributeValue::Reference(data),
+                TYPE_INT_DEC | TYPE_INT_HEX => AttributeValue::Int((data as i32).into()),
+                TYPE_INT_BOOLEAN => AttributeValue::Bool(data != 0),
+                _ => match self.string(raw) {
+                    Some(raw) => AttributeValue::String(raw),
+                    None => AttributeValue::Other { data_type, data },
+                },
+            };
+            attributes.push(Attribute {
+                name,
+                resource_id,
+                value,
+            });
+        }
+        Ok(Element {
+            name,
+            attributes,
+            children: Vec::new(),
+        })
+    }
+}
+
+/// Parses a binary XML file, such as the `AndroidManifest.xml` of an APK,
+/// into its root element. Namespaces and text are dropped.
+pub fn parse(bytes: &[u8]) -> Result<Element, AxmlError> {
+    let file_type = read_u16(bytes, 0)?;
+    if file_type != RES_XML_TYPE {
+        return Err(AxmlError::InvalidChunk {
+            offset: 0,
+            chunk_type: file_type,
+        });
+    }
+    let mut parser = Parser {
+        strings: Vec::new(),
+        resource_ids: Vec::new(),
+    };
+    let mut stack: Vec<Element> = Vec::new();
+    let mut root = None;
+    let mut offset = read_u16(bytes, 2)? as usize;
+    while offset + 8 <= bytes.len() {
+        let chunk_type = read_u16(bytes, offset)?;
+        let header_size = read_u16(bytes, offset + 2)? as usize;
+        let size = read_u32(bytes, offset + 4)? as usize;
+        if size < 8 || header_size > size {
+            return Err(AxmlError::InvalidChunk { offset, chunk_type });
+        }
+        let chunk = bytes
+            .get(offset..offset + size)
+            .ok_or(AxmlError::Truncated { offset })?;
+        match chunk_type {
+            RES_STRING_POOL_TYPE => parser.strings = string_pool(chunk)?,
+            RES_XML_RESOURCE_MAP_TYPE => {
+                parser.resource_ids = chunk[header_size..]
+                    .chunks_exact(4)
+                    .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect()
+            }
+            RES_XML_START_ELEMENT_TYPE => stack.push(parser.start_element(chunk)?),
+            RES_XML_END_ELEMENT_TYPE => {
+                let element = stack.pop().ok_or(AxmlError::Unbalanced { offset })?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => root = root.or(Some(element)),
+                }
+            }
+            // Namespaces, text and unknown chunks
+            _ => {}
+        }
+        offset += size;
+    }
+    // Close the elements left open by a truncated file
+    while let Some(element) = stack.pop() {
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(element),
+            None => root = root.or(Some(element)),
+        }
+    }
+    root.ok_or(AxmlError::NoRoot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    enum Event {
+        /// Element name and `(name, type, data)` attributes.
+        Start(u32, Vec<(u32, u8, u32)>),
+        End,
+    }
+
+    fn chunk(chunk_type: u16, header_size: u16, body: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&chunk_type.to_le_bytes());
+        bytes.extend_from_slice(&header_size.to_le_bytes());
+        bytes.extend_from_slice(&(8 + body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    fn encode(strings: &[&str], resource_ids: &[u32], events: &[Event]) -> Vec<u8> {
+        let mut offsets = Vec::new();
+        let mut data = Vec::new();
+        for s in strings {
+            offsets.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            let units: Vec<u16> = s.encode_utf16().collect();
+            data.extend_from_slice(&(units.len() as u16).to_le_bytes());
+            for unit in units.iter().chain(&[0]) {
+                data.extend_from_slice(&unit.to_le_bytes());
+            }
+        }
+        let mut pool = Vec::new();
+        for field in &[strings.len() as u32, 0, 0, 28 + offsets.len() as u32, 0] {
+            pool.extend_from_slice(&field.to_le_bytes());
+        }
+        pool.extend(offsets);
+        pool.extend(data);
+
+        let mut body = chunk(RES_STRING_POOL_TYPE, 28, &pool);
+        let ids: Vec<u8> = resource_ids
+            .iter()
+            .flat_map(|id| id.to_le_bytes())
+            .collect();
+        body.extend(chunk(RES_XML_RESOURCE_MAP_TYPE, 8, &ids));
+        for event in events {
+            let mut node = vec![0u8; 8];
+            node[4..].copy_from_slice(&NO_INDEX.to_le_bytes());
+            match event {
+                Event::Start(name, attributes) => {
+                    node.extend_from_slice(&NO_INDEX.to_le_bytes());
+                    node.extend_from_slice(&name.to_le_bytes());
+                    for field in &[20u16, 20, attributes.len() as u16, 0, 0, 0] {
+                        node.extend_from_slice(&field.to_le_bytes());
+                    }
+                    for (name, data_type, data) in attributes {
+                        node.extend_from_slice(&NO_INDEX.to_le_bytes());
+                        node.extend_from_slice(&name.to_le_bytes());
+                        node.extend_from_slice(&NO_INDEX.to_le_bytes());
+                        node.extend_from_slice(&[8, 0, 0, *data_type]);
+                        node.extend_from_slice(&data.to_le_bytes());
+                    }
+                    body.extend(chunk(RES_XML_START_ELEMENT_TYPE, 16, &node));
+                }
+                Event::End => {
+                    node.extend_from_slice(&[0; 8]);
+                    body.extend(chunk(RES_XML_END_ELEMENT_TYPE, 16, &node));
+                }
+            }
+        }
+        chunk(RES_XML_TYPE, 8, &body)
+    }
+
+    #[test]
+    fn test_parse() {
+        // The attribute names are obfuscated but the resource map is intact
+        let strings = ["", "x", "package", "manifest", "com.example", "uses-sdk"];
+        let bytes = encode(
+            &strings,
+            &[0x0101_021b, 0x0101_020c],
+            &[
+                Event::Start(3, vec![(2, TYPE_STRING, 4), (0, TYPE_INT_DEC, 7)]),
+                Event::Start(5, vec![(1, TYPE_INT_DEC, 21)]),
+                Event::End,
+                Event::End,
+            ],
+        );
+        let root = parse(&bytes).unwrap();
+        assert_eq!(root.name, "manifest");
+        assert_eq!(
+            root.string_attribute("package"),
+            Some(String::from("com.example"))
+        );
+        assert_eq!(root.attribute("versionCode"), Some(&AttributeValue::Int(7)));
+        let sdk: Vec<&Element> = root.children_named("uses-sdk").collect();
+        assert_eq!(sdk.len(), 1);
+        assert_eq!(
+            sdk[0].attribute("minSdkVersion"),
+            Some(&AttributeValue::Int(21))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse(&[0x03]), Err(AxmlError::Truncated { offset: 0 }));
+        assert_eq!(
+            parse(&[0x01, 0x00, 0x08, 0x00, 0x08, 0x00, 0x00, 0x00]),
+            Err(AxmlError::InvalidChunk {
+                offset: 0,
+                chunk_type: 1
+            })
+        );
+        let bytes = encode(&["a"], &[], &[Event::End]);
+        assert!(matches!(parse(&bytes), Err(AxmlError::Unbalanced { .. })));
+        assert_eq!(parse(&encode(&[], &[], &[])), Err(AxmlError::NoRoot));
+    }
+}
@@ -1,3 +1,90 @@
+use libradar::apk::Apk;
+use libradar::database::Trainer;
+use libradar::options::Options;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Extensions of the inputs `Apk::from_path` can load. Aars are left out as
+/// they usually hold Java class files rather than dex files.
+const INPUT_EXTENSIONS: &[&str] = &["apk", "dex", "jar", "apks", "xapk", "aab"];
+
+fn find_apks(dir: &Path, apks: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_apks(&path, apks)?;
+        } else if path
+            .extension()
+            .and_then(OsStr::to_str)
+            .is_some_and(|ext| INPUT_EXTENSIONS.contains(&ext))
+        {
+            apks.push(path);
+        }
+    }
+    Ok(())
+}
+
 fn main() {
-    panic!("TODO");
+    let args: Vec<_> = std::env::args().collect();
+    if args.len() < 3 {
+        println!("At least two arguments are needed in order to work.");
+        println!(
+            "Usage: {} <apk directory> <output database> [--min-apps N] [--api-list FILE] \
+             [--threshold X] [--metric jaccard|cosine] [--partial]",
+            &*args[0]
+        );
+        return;
+    }
+
+    let allowed = [
+        "--min-apps",
+        "--api-list",
+        "--threshold",
+        "--metric",
+        "--partial",
+    ];
+    let options = match Options::parse(&args[3..], &allowed) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let mut apks = Vec::new();
+    find_apks(Path::new(&*args[1]), &mut apks).expect("Failed to read the apk directory");
+    apks.sort();
+
+    let mut trainer = Trainer::new(options.min_apps, options.apis)
+        .with_matcher(options.matcher)
+        .with_partial_dex(options.partial);
+    for path in &apks {
+        // Reference builds of a known library version are named
+        // `package@version.<ext>`, e.g. `com.squareup.okhttp3@3.12.0.jar`.
+        let reference = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.rsplit_once('@'));
+        let result = Apk::from_path(path).and_then(|apk| match reference {
+            Some((library, version)) => trainer.add_reference(&apk, library, version),
+            None => trainer.add_apk(&apk),
+        });
+        match result {
+            Ok(errors) => {
+                for e in errors {
+                    eprintln!("Skipping a dex file of {:?}: {}", path, e);
+                }
+            }
+            Err(e) => eprintln!("Skipping {:?}: {}", path, e),
+        }
+    }
+
+    let db = trainer.into_database();
+    db.save(&*args[2]).expect("Failed to write the database");
+    println!(
+        "{} libraries found in {} apks",
+        db.libraries.len(),
+        apks.len()
+    );
 }
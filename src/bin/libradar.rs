@@ -1,33 +1,55 @@
 use libradar::apk::*;
-use libradar::callgraph::get_invoked_methods_names;
-
-fn print_info_of_apk(apk: Apk) {
-    for dex in apk.dex_files {
-        for class in dex.classes() {
-            let class = class.expect("Failed to load class");
-            let class_name = class.jtype().type_descriptor().to_string();
-            println!("class {}", class_name);
-            for method in class.methods() {
-                println!("  method {}", method.name().to_string());
-                if let Some(code) = method.code() {
-                    for target in get_invoked_methods_names(&code, &dex) {
-                        println!("    {}", target);
-                    }
-                }
-            }
-        }
-    }
-}
+use libradar::database::Database;
+use libradar::detection::Detector;
+use libradar::options::Options;
 
 fn main() {
     let args: Vec<_> = std::env::args().collect();
-    if args.len() < 2 {
-        println!("An argument is needed in order to work.");
-        println!("Usage: {} <apk filename>", &*args[0]);
+    if args.len() < 3 {
+        println!("Two arguments are needed in order to work.");
+        println!(
+            "Usage: {} <database> <apk filename> [--api-list FILE] [--threshold X] \
+             [--metric jaccard|cosine] [--partial]",
+            &*args[0]
+        );
         return;
     }
 
-    let apk = Apk::from_path(&*args[1]).expect("Failed to open APK");
+    let allowed = ["--api-list", "--threshold", "--metric", "--partial"];
+    let options = match Options::parse(&args[3..], &allowed) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let db = Database::from_path(&*args[1]).expect("Failed to load the database");
+    let apk = Apk::from_path(&*args[2]).expect("Failed to open APK");
 
-    print_info_of_apk(apk);
+    let detector = Detector::new(&db, options.apis).with_matcher(options.matcher);
+    let detections = if options.partial {
+        let (detections, errors) = detector
+            .detect_partial(&apk)
+            .expect("Failed to analyze APK");
+        for e in errors {
+            eprintln!("Skipping a dex file: {}", e);
+        }
+        detections
+    } else {
+        detector.detect(&apk).expect("Failed to analyze APK")
+    };
+    println!("library\tversion\tconfidence\tpackage\tsimilarity\tclasses\tmethods");
+    for d in detections {
+        println!(
+            "{}\t{}\t{:.2}\t{}\t{:.2}\t{}\t{}",
+            d.library, d.version, d.confidence, d.package, d.similarity, d.classes, d.methods
+        );
+        for (api, count) in &d.diff.only_left {
+            println!("  - {} x{}", api, count);
+        }
+        for (api, count) in &d.diff.only_right {
+            println!("  + {} x{}", api, count);
+        }
+    }
 }
@@ -1,45 +1,518 @@
-use crate::disass::disassemble;
+use crate::apk::{Apk, DexFile, DexLoadError};
+use crate::cfg::ControlFlowGraph;
+use crate::dataflow::{ConstantPool, ConstantPropagation, Value};
+use crate::disass::{disassemble, DecodedInst, InvokeKind};
+use crate::hierarchy::{member_name, ClassHierarchy};
+use crate::manifest::Manifest;
 use dex;
 use dex::code::CodeItem;
 use dex::method::MethodIdItem;
 use dex::Dex;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::convert::TryInto;
 
+/// Methods invoked by `code`. Invokes of method indices missing from the dex
+/// are skipped.
 pub fn get_invoked_methods<'a>(
     code: &'a CodeItem,
     dex: &'a Dex<Vec<u8>>,
 ) -> impl Iterator<Item = MethodIdItem> + 'a {
-    disassemble(code).filter_map(move |ins| {
+    disassemble(code).flatten().filter_map(move |ins| {
         if ins.is_invoke() {
-            let target = dex
-                .get_method_item(ins.invoke_target().try_into().unwrap())
-                .unwrap();
-            Some(target)
+            dex.get_method_item(ins.invoke_target().try_into().ok()?)
+                .ok()
         } else {
             None
         }
     })
 }
 
+/// Invoke instruction at `addr`, in code units. `in_handler` is set when the
+/// call is made from the code of an exception handler.
+pub struct InvokeSite {
+    pub addr: usize,
+    pub method: MethodIdItem,
+    pub in_handler: bool,
+}
+
+pub fn get_invoke_sites(code: &CodeItem, dex: &Dex<Vec<u8>>) -> Vec<InvokeSite> {
+    let handler_insts: HashSet<usize> = match ControlFlowGraph::from_code(code) {
+        Ok(cfg) => cfg
+            .handler_blocks()
+            .into_iter()
+            .flat_map(|block| cfg.block(block).insts.clone())
+            .collect(),
+        Err(_) => HashSet::new(),
+    };
+    disassemble(code)
+        .flatten()
+        .filter(|ins| ins.is_invoke())
+        .filter_map(|ins| {
+            let method = dex.get_method_item(ins.invoke_target() as u64).ok()?;
+            Some(InvokeSite {
+                addr: ins.addr(),
+                method,
+                in_handler: handler_insts.contains(&ins.addr()),
+            })
+        })
+        .collect()
+}
+
+/// How likely a reflective call is to reach its target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Confidence {
+    /// The target is not defined in the APK, e.g. it is in the framework or
+    /// in code loaded at runtime.
+    Low,
+    /// The class defines or inherits several methods with that name.
+    Medium,
+    /// The class defines or inherits a single method with that name.
+    High,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CallKind {
+    Direct,
+    Reflective(Confidence),
+    /// Possible target of a virtual or interface call, found with the class
+    /// hierarchy.
+    Dispatch,
+}
+
+/// Call made at `addr` to `target`, in the `Lclass;->name` form.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CallEdge {
+    pub addr: usize,
+    pub target: String,
+    pub kind: CallKind,
+}
+
+fn reflective_confidence(class: &str, name: &str, hierarchy: &ClassHierarchy) -> Confidence {
+    match hierarchy.methods_named(class, name).len() {
+        0 => Confidence::Low,
+        1 => Confidence::High,
+        _ => Confidence::Medium,
+    }
+}
+
+/// Calls made through `Method.invoke`, `Constructor.newInstance` and
+/// `Class.newInstance` whose target could be resolved from constant class
+/// and method names. Targets are looked up in `hierarchy`, which should cover
+/// every dex file of the APK.
+pub fn get_reflective_calls(
+    code: &CodeItem,
+    dex: &Dex<Vec<u8>>,
+    hierarchy: &ClassHierarchy,
+) -> Vec<CallEdge> {
+    let analysis = match ConstantPropagation::from_code(code, dex) {
+        Ok(analysis) => analysis,
+        Err(_) => return Vec::new(),
+    };
+    analysis
+        .invokes()
+        .iter()
+        .filter_map(|invoke| {
+            let (class, name) = match (invoke.method.as_deref()?, invoke.args.first()?) {
+                (
+                    "Ljava/lang/reflect/Method;->invoke(Ljava/lang/Object;[Ljava/lang/Object;)Ljava/lang/Object;"
+                    | "Ljava/lang/reflect/Constructor;->newInstance([Ljava/lang/Object;)Ljava/lang/Object;",
+                    Value::Method { class, name },
+                ) => (class, name.as_str()),
+                ("Ljava/lang/Class;->newInstance()Ljava/lang/Object;", Value::Class(class)) => {
+                    (class, "<init>")
+                }
+                _ => return None,
+            };
+            Some(CallEdge {
+                addr: invoke.addr,
+                target: format!("{}->{}", class, name),
+                kind: CallKind::Reflective(reflective_confidence(class, name, hierarchy)),
+            })
+        })
+        .collect()
+}
+
+/// Direct and resolved reflective calls of `code`, by address.
+pub fn get_call_edges(
+    code: &CodeItem,
+    dex: &Dex<Vec<u8>>,
+    hierarchy: &ClassHierarchy,
+) -> Vec<CallEdge> {
+    let mut edges: Vec<CallEdge> = get_invoke_sites(code, dex)
+        .into_iter()
+        .filter_map(|site| {
+            Some(CallEdge {
+                addr: site.addr,
+                target: method_name(&site.method, dex)?,
+                kind: CallKind::Direct,
+            })
+        })
+        .collect();
+    edges.extend(get_reflective_calls(code, dex, hierarchy));
+    edges.sort_by_key(|edge| edge.addr);
+    edges
+}
+
 pub fn get_invoked_methods_names<'a>(
     code: &'a CodeItem,
     dex: &'a Dex<Vec<u8>>,
 ) -> impl Iterator<Item = String> + 'a {
-    get_invoked_methods(code, dex).map(move |target| {
-        let method_name = dex.get_string(target.name_idx()).unwrap().to_string();
-        let class_name = dex
-            .get_type(target.class_idx().into())
-            .unwrap()
-            .type_descriptor()
-            .to_string();
-        format!("{}->{}", class_name, method_name)
-    })
+    get_invoked_methods(code, dex).filter_map(move |target| method_name(&target, dex))
+}
+
+// The lookups below return `None` when the dex references an index it does
+// not define.
+
+/// Type descriptor of the class that declares `method`, e.g. `Ljava/lang/String;`.
+pub fn method_class_name(method: &MethodIdItem, dex: &Dex<Vec<u8>>) -> Option<String> {
+    let class = dex.get_type(method.class_idx().into()).ok()?;
+    Some(class.type_descriptor().to_string())
+}
+
+/// Prototype descriptor of `method`, e.g. `(Ljava/lang/String;I)V`.
+pub fn method_signature(method: &MethodIdItem, dex: &Dex<Vec<u8>>) -> Option<String> {
+    let proto = dex.get_proto_item(method.proto_idx().into()).ok()?;
+    let params: Vec<String> = dex
+        .get_interfaces(proto.params_off())
+        .ok()?
+        .unwrap_or_default()
+        .iter()
+        .map(|param| param.type_descriptor().to_string())
+        .collect();
+    let ret = dex.get_type(proto.return_type()).ok()?;
+    Some(format!("({}){}", params.concat(), ret.type_descriptor()))
+}
+
+/// Fully qualified name of `method` in the `Lclass;->name` form.
+pub fn method_name(method: &MethodIdItem, dex: &Dex<Vec<u8>>) -> Option<String> {
+    let name = dex.get_string(method.name_idx()).ok()?;
+    Some(format!("{}->{}", method_class_name(method, dex)?, name))
+}
+
+/// Method of the call graph, in the `Lclass;->name(params)return` form.
+/// Reflective targets that are not defined in the APK have no prototype.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CallNode {
+    pub name: String,
+    /// Not defined in any dex file of the APK.
+    pub external: bool,
+}
+
+/// Edge to or from the node with index `node`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CallGraphEdge {
+    pub node: usize,
+    pub kind: CallKind,
+}
+
+/// Calls between the methods of all the dex files of an APK.
+#[derive(Debug, Clone, Default)]
+pub struct CallGraph {
+    nodes: Vec<CallNode>,
+    by_name: HashMap<String, usize>,
+    callees: Vec<Vec<CallGraphEdge>>,
+    callers: Vec<Vec<CallGraphEdge>>,
+    hierarchy: ClassHierarchy,
+}
+
+/// How virtual and interface calls are resolved when building the graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dispatch {
+    /// Only the method named by the instruction.
+    Declared,
+    /// Also every override in a concrete subtype of the receiver type.
+    ClassHierarchy,
+    /// Also the overrides of the subtypes instantiated somewhere in the APK,
+    /// with `new-instance` or reflection, or by the framework as components.
+    RapidType,
+}
+
+/// Types instantiated in any method of `dex_files`, directly or through
+/// `Class.newInstance` and `Constructor.newInstance`, and the components of
+/// `manifest`.
+fn instantiated_types(
+    dex_files: &[&DexFile],
+    hierarchy: &ClassHierarchy,
+    manifest: Option<&Manifest>,
+) -> HashSet<String> {
+    let mut types: HashSet<String> = manifest
+        .map(Manifest::component_classes)
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    for dex in dex_files {
+        for class in dex.classes().flatten() {
+            for code in class.methods().filter_map(|method| method.code()) {
+                let mut reflection = false;
+                for ins in disassemble(code).flatten() {
+                    match ins.decode() {
+                        DecodedInst::NewInstance { type_idx, .. } => {
+                            if let Ok(jtype) = dex.get_type(type_idx) {
+                                types.insert(jtype.type_descriptor().to_string());
+                            }
+                        }
+                        DecodedInst::Invoke { method_idx, .. } => {
+                            let target = ConstantPool::method(*dex, method_idx).unwrap_or_default();
+                            reflection |= target.starts_with("Ljava/lang/Class;->newInstance")
+                                || target
+                                    .starts_with("Ljava/lang/reflect/Constructor;->newInstance");
+                        }
+                        _ => {}
+                    }
+                }
+                if !reflection {
+                    continue;
+                }
+                for call in get_reflective_calls(code, dex, hierarchy) {
+                    if let Some(class) = call.target.strip_suffix("-><init>") {
+                        types.insert(class.to_string());
+                    }
+                }
+            }
+        }
+    }
+    types
+}
+
+impl CallGraph {
+    pub fn from_apk(apk: &Apk, dispatch: Dispatch) -> Result<Self, DexLoadError> {
+        Ok(Self::from_dex_files(
+            &apk.dex_files()?,
+            apk.manifest.as_ref(),
+            dispatch,
+        ))
+    }
+
+    /// With `Dispatch::RapidType`, the components of `manifest` count as
+    /// instantiated.
+    pub fn from_dex_files(
+        dex_files: &[&DexFile],
+        manifest: Option<&Manifest>,
+        dispatch: Dispatch,
+    ) -> Self {
+        let hierarchy = ClassHierarchy::from_dex_files(dex_files);
+        let instantiated = match dispatch {
+            Dispatch::RapidType => Some(instantiated_types(dex_files, &hierarchy, manifest)),
+            _ => None,
+        };
+        let mut graph = CallGraph::default();
+        for dex in dex_files {
+            for class in dex.classes().flatten() {
+                let class_name = class.jtype().type_descriptor().to_string();
+                for method in class.methods() {
+                    let caller =
+                        graph.add_node(&format!("{}->{}", class_name, member_name(method)), false);
+                    if let Some(code) = method.code() {
+                        graph.add_calls(caller, code, dex, &hierarchy, dispatch, &instantiated);
+                    }
+                }
+            }
+        }
+        graph.hierarchy = hierarchy;
+        graph
+    }
+
+    fn add_calls(
+        &mut self,
+        caller: usize,
+        code: &CodeItem,
+        dex: &DexFile,
+        hierarchy: &ClassHierarchy,
+        dispatch: Dispatch,
+        instantiated: &Option<HashSet<String>>,
+    ) {
+        let mut reflection = false;
+        for ins in disassemble(code).flatten() {
+            let (kind, method_idx) = match ins.decode() {
+                DecodedInst::Invoke {
+                    kind, method_idx, ..
+                } => (kind, method_idx),
+                _ => continue,
+            };
+            let target = match ConstantPool::method(dex, method_idx) {
+                Some(target) => target,
+                None => continue,
+            };
+            reflection |= target.starts_with("Ljava/lang/reflect/")
+                || target.starts_with("Ljava/lang/Class;->newInstance");
+            let (class, member) = match target.split_once("->") {
+                Some(parts) => parts,
+                None => continue,
+            };
+            let direct = match hierarchy.resolve_method(class, member) {
+                Some(class) => self.add_node(&format!("{}->{}", class, member), false),
+                None => self.add_node(&target, true),
+            };
+            self.add_edge(caller, direct, CallKind::Direct);
+
+            let virtual_call = kind == InvokeKind::Virtual || kind == InvokeKind::Interface;
+            if dispatch == Dispatch::Declared || !virtual_call {
+                continue;
+            }
+            for name in hierarchy.dispatch_targets(class, member, instantiated.as_ref()) {
+                let callee = self.add_node(&name, false);
+                if callee != direct {
+                    self.add_edge(caller, callee, CallKind::Dispatch);
+                }
+            }
+        }
+        if !reflection {
+            return;
+        }
+        for call in get_reflective_calls(code, dex, hierarchy) {
+            let (class, name) = match call.target.split_once("->") {
+                Some(parts) => parts,
+                None => continue,
+            };
+            // `getMethod` also finds the public methods of the superclasses
+            let defined = hierarchy.methods_named(class, name);
+            if defined.is_empty() {
+                let callee = self.add_node(&call.target, true);
+                self.add_edge(caller, callee, call.kind);
+            }
+            for name in defined {
+                let callee = self.add_node(&name, false);
+                self.add_edge(caller, callee, call.kind);
+            }
+        }
+    }
+
+    fn add_node(&mut self, name: &str, external: bool) -> usize {
+        if let Some(&node) = self.by_name.get(name) {
+            // A method first seen as a callee may be defined later on
+            self.nodes[node].external &= external;
+            return node;
+        }
+        let node = self.nodes.len();
+        self.nodes.push(CallNode {
+            name: name.to_string(),
+            external,
+        });
+        self.by_name.insert(name.to_string(), node);
+        self.callees.push(Vec::new());
+        self.callers.push(Vec::new());
+        node
+    }
+
+    fn add_edge(&mut self, from: usize, to: usize, kind: CallKind) {
+        let edge = CallGraphEdge { node: to, kind };
+        if !self.callees[from].contains(&edge) {
+            self.callees[from].push(edge);
+            self.callers[to].push(CallGraphEdge { node: from, kind });
+        }
+    }
+
+    pub fn nodes(&self) -> &[CallNode] {
+        &self.nodes
+    }
+
+    pub fn node(&self, index: usize) -> &CallNode {
+        &self.nodes[index]
+    }
+
+    /// Index of the method named `name`, in the `Lclass;->name(params)return`
+    /// form.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn callees(&self, index: usize) -> &[CallGraphEdge] {
+        &self.callees[index]
+    }
+
+    pub fn callers(&self, index: usize) -> &[CallGraphEdge] {
+        &self.callers[index]
+    }
+
+    pub fn hierarchy(&self) -> &ClassHierarchy {
+        &self.hierarchy
+    }
+
+    /// Lifecycle callbacks of the components declared in `manifest`, to use
+    /// as roots of the reachability analysis.
+    pub fn entry_points(&self, manifest: &Manifest) -> Vec<usize> {
+        manifest
+            .entry_points(&self.hierarchy)
+            .iter()
+            .filter_map(|name| self.find(name))
+            .collect()
+    }
+
+    /// Strongly connected components, computed with Tarjan's algorithm.
+    /// Components come in reverse topological order: callees first.
+    pub fn strongly_connected_components(&self) -> Vec<Vec<usize>> {
+        let count = self.nodes.len();
+        let mut index = vec![usize::MAX; count];
+        let mut low = vec![0; count];
+        let mut on_stack = vec![false; count];
+        let mut stack = Vec::new();
+        let mut components = Vec::new();
+        let mut next = 0;
+        for root in 0..count {
+            if index[root] != usize::MAX {
+                continue;
+            }
+            index[root] = next;
+            low[root] = next;
+            next += 1;
+            stack.push(root);
+            on_stack[root] = true;
+            // Iterative depth-first search keeping the next callee to visit
+            let mut work = vec![(root, 0)];
+            while let Some((node, i)) = work.pop() {
+                if let Some(edge) = self.callees[node].get(i) {
+                    work.push((node, i + 1));
+                    let callee = edge.node;
+                    if index[callee] == usize::MAX {
+                        index[callee] = next;
+                        low[callee] = next;
+                        next += 1;
+                        stack.push(callee);
+                        on_stack[callee] = true;
+                        work.push((callee, 0));
+                    } else if on_stack[callee] {
+                        low[node] = low[node].min(index[callee]);
+                    }
+                    continue;
+                }
+                if low[node] == index[node] {
+                    let mut component = Vec::new();
+                    while let Some(member) = stack.pop() {
+                        on_stack[member] = false;
+                        component.push(member);
+                        if member == node {
+                            break;
+                        }
+                    }
+                    component.sort_unstable();
+                    components.push(component);
+                }
+                if let Some(&(parent, _)) = work.last() {
+                    low[parent] = low[parent].min(low[node]);
+                }
+            }
+        }
+        components
+    }
+
+    /// Nodes reachable from `roots`, including the roots.
+    pub fn reachable_from(&self, roots: &[usize]) -> BTreeSet<usize> {
+        let mut reached: BTreeSet<usize> = roots.iter().copied().collect();
+        let mut stack: Vec<usize> = roots.to_vec();
+        while let Some(node) = stack.pop() {
+            for edge in &self.callees[node] {
+                if reached.insert(edge.node) {
+                    stack.push(edge.node);
+                }
+            }
+        }
+        reached
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::apk::Apk;
 
     #[test]
     fn test_callgraph() {
@@ -128,7 +601,8 @@ mod tests {
             "Lcom/github/mertakdut/exception/ReadingException;-><init>",
         ];
         let apk = Apk::from_path("resources/test01.apk").expect("Can't open test apk file");
-        let class = apk.dex_files[0]
+        let dex = apk.dex(0).expect("Failed to load dex");
+        let class = dex
             .find_class_by_name("Lcom/github/mertakdut/Reader;")
             .expect("Failed to load class")
             .unwrap();
@@ -137,7 +611,7 @@ mod tests {
             if method.name() == "fillContent" {
                 if let Some(code) = method.code() {
                     found = true;
-                    for (i, target) in get_invoked_methods_names(&code, &apk.dex_files[0]).enumerate() {
+                    for (i, target) in get_invoked_methods_names(&code, dex).enumerate() {
                         assert_eq!(target, calls[i]);
                     }
                 }
@@ -149,4 +623,39 @@ mod tests {
             "The test method to disassemble could not be found. Test missed!"
         );
     }
+
+    fn graph(names: &[&str], edges: &[(usize, usize)]) -> CallGraph {
+        let mut graph = CallGraph::default();
+        for name in names {
+            graph.add_node(name, false);
+        }
+        for (from, to) in edges {
+            graph.add_edge(*from, *to, CallKind::Direct);
+        }
+        graph
+    }
+
+    #[test]
+    fn test_graph_queries() {
+        let mut graph = graph(
+            &["La;->a()V", "La;->b()V", "La;->c()V", "La;->d()V"],
+            &[(0, 1), (1, 2), (2, 1), (1, 1), (3, 0)],
+        );
+        assert_eq!(graph.find("La;->c()V"), Some(2));
+        let callers: Vec<usize> = graph.callers(1).iter().map(|e| e.node).collect();
+        assert_eq!(callers, vec![0, 2, 1]);
+        assert_eq!(
+            graph.strongly_connected_components(),
+            vec![vec![1, 2], vec![0], vec![3]]
+        );
+        assert_eq!(
+            graph.reachable_from(&[0]).into_iter().collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+
+        let external = graph.add_node("Ljava/lang/Object;-><init>()V", true);
+        assert!(graph.node(external).external);
+        assert_eq!(graph.add_node("La;->a()V", true), 0);
+        assert!(!graph.node(0).external);
+    }
 }
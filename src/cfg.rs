@@ -0,0 +1,375 @@
+use crate::disass::{code_bytes, DisassError, ExceptionTable, InstIterator};
+use dex::code::CodeItem;
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EdgeKind {
+    Normal,
+    Exceptional,
+}
+
+/// Edge to or from the block with index `block`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Edge {
+    pub block: usize,
+    pub kind: EdgeKind,
+}
+
+/// Straight-line code between `start` and `end` (exclusive), in code units.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize,
+    /// Addresses of the instructions of the block.
+    pub insts: Vec<usize>,
+    pub successors: Vec<Edge>,
+    pub predecessors: Vec<Edge>,
+}
+
+/// Basic blocks of a method. The entry block is always the first one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+    blocks: Vec<BasicBlock>,
+    by_start: HashMap<usize, usize>,
+}
+
+/// Immediate dominators of the blocks reachable from the entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DominatorTree {
+    idom: Vec<Option<usize>>,
+}
+
+impl ControlFlowGraph {
+    pub fn from_code(code: &CodeItem) -> Result<Self, DisassError> {
+        Self::build(code_bytes(code), &ExceptionTable::from_code(code))
+    }
+
+    /// Builds the graph of the instructions in `code`. Payloads are data and
+    /// are left out of the blocks.
+    pub fn build(code: &[u8], tries: &ExceptionTable) -> Result<Self, DisassError> {
+        let mut insts = Vec::new();
+        for inst in InstIterator::new(code, code.len()) {
+            let inst = inst?;
+            if inst.payload().is_none() {
+                let ends_block = inst.is_goto()
+                    || inst.is_branch()
+                    || inst.is_switch()
+                    || inst.is_return()
+                    || inst.is_throw();
+                insts.push((
+                    inst.addr(),
+                    inst.next_addr(),
+                    inst.successors(code)?,
+                    ends_block,
+                ));
+            }
+        }
+        if insts.is_empty() {
+            return Ok(ControlFlowGraph {
+                blocks: Vec::new(),
+                by_start: HashMap::new(),
+            });
+        }
+
+        let mut leaders = BTreeSet::new();
+        leaders.insert(insts[0].0);
+        for (_, _, successors, ends_block) in &insts {
+            if *ends_block {
+                leaders.extend(successors.iter().copied());
+            }
+        }
+        for block in tries.blocks() {
+            leaders.insert(block.start);
+            leaders.insert(block.end);
+        }
+        leaders.extend(tries.handler_addrs());
+
+        let mut blocks: Vec<BasicBlock> = Vec::new();
+        let mut exits: Vec<&Vec<usize>> = Vec::new();
+        for (i, (addr, next, successors, _)) in insts.iter().enumerate() {
+            if i == 0 || leaders.contains(addr) || insts[i - 1].3 {
+                blocks.push(BasicBlock {
+                    start: *addr,
+                    end: *next,
+                    insts: Vec::new(),
+                    successors: Vec::new(),
+                    predecessors: Vec::new(),
+                });
+                exits.push(successors);
+            }
+            let block = blocks.last_mut().unwrap();
+            block.insts.push(*addr);
+            block.end = *next;
+            *exits.last_mut().unwrap() = successors;
+        }
+
+        let by_start: HashMap<usize, usize> = blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| (block.start, i))
+            .collect();
+        let mut cfg = ControlFlowGraph { blocks, by_start };
+        for (from, successors) in exits.into_iter().enumerate() {
+            for to in successors {
+                if let Some(&to) = cfg.by_start.get(to) {
+                    cfg.add_edge(from, to, EdgeKind::Normal);
+                }
+            }
+        }
+        for from in 0..cfg.blocks.len() {
+            for handler in tries.handlers_at(cfg.blocks[from].start) {
+                if let Some(&to) = cfg.by_start.get(&handler.addr) {
+                    cfg.add_edge(from, to, EdgeKind::Exceptional);
+                }
+            }
+        }
+        Ok(cfg)
+    }
+
+    fn add_edge(&mut self, from: usize, to: usize, kind: EdgeKind) {
+        let edge = Edge { block: to, kind };
+        if !self.blocks[from].successors.contains(&edge) {
+            self.blocks[from].successors.push(edge);
+            self.blocks[to]
+                .predecessors
+                .push(Edge { block: from, kind });
+        }
+    }
+
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    pub fn block(&self, index: usize) -> &BasicBlock {
+        &self.blocks[index]
+    }
+
+    /// Index of the block that contains the instruction at `addr`.
+    pub fn block_at(&self, addr: usize) -> Option<usize> {
+        self.blocks
+            .iter()
+            .position(|block| block.insts.contains(&addr))
+    }
+
+    pub fn successors(&self, index: usize) -> &[Edge] {
+        &self.blocks[index].successors
+    }
+
+    pub fn predecessors(&self, index: usize) -> &[Edge] {
+        &self.blocks[index].predecessors
+    }
+
+    /// Blocks reachable from the entry, in reverse post-order.
+    pub fn reverse_post_order(&self) -> Vec<usize> {
+        let mut order = Vec::new();
+        if self.blocks.is_empty() {
+            return order;
+        }
+        let mut visited = vec![false; self.blocks.len()];
+        // Iterative depth-first search keeping the next successor to visit
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((block, next)) = stack.pop() {
+            if let Some(edge) = self.blocks[block].successors.get(next) {
+                stack.push((block, next + 1));
+                if !visited[edge.block] {
+                    visited[edge.block] = true;
+                    stack.push((edge.block, 0));
+                }
+            } else {
+                order.push(block);
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// Blocks that only run after an exception was caught, i.e. reachable
+    /// from the entry but not through normal edges alone.
+    pub fn handler_blocks(&self) -> BTreeSet<usize> {
+        if self.blocks.is_empty() {
+            return BTreeSet::new();
+        }
+        let mut normal = vec![false; self.blocks.len()];
+        let mut stack = vec![0];
+        normal[0] = true;
+        while let Some(block) = stack.pop() {
+            for edge in &self.blocks[block].successors {
+                if edge.kind == EdgeKind::Normal && !normal[edge.block] {
+                    normal[edge.block] = true;
+                    stack.push(edge.block);
+                }
+            }
+        }
+        self.reverse_post_order()
+            .into_iter()
+            .filter(|block| !normal[*block])
+            .collect()
+    }
+
+    /// Dominator tree, computed with the iterative algorithm of Cooper,
+    /// Harvey and Kennedy.
+    pub fn dominators(&self) -> DominatorTree {
+        let order = self.reverse_post_order();
+        let mut rank = vec![usize::MAX; self.blocks.len()];
+        for (i, block) in order.iter().enumerate() {
+            rank[*block] = i;
+        }
+
+        let mut idom: Vec<Option<usize>> = vec![None; self.blocks.len()];
+        if let Some(&entry) = order.first() {
+            idom[entry] = Some(entry);
+        }
+        let intersect = |idom: &[Option<usize>], mut a: usize, mut b: usize| {
+            while a != b {
+                while rank[a] > rank[b] {
+                    a = idom[a].unwrap();
+                }
+                while rank[b] > rank[a] {
+                    b = idom[b].unwrap();
+                }
+            }
+            a
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in order.iter().skip(1) {
+                let mut new_idom = None;
+                for pred in &self.blocks[block].predecessors {
+                    if idom[pred.block].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => pred.block,
+                        Some(current) => intersect(&idom, pred.block, current),
+                    });
+                }
+                if new_idom.is_some() && idom[block] != new_idom {
+                    idom[block] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        DominatorTree { idom }
+    }
+}
+
+impl DominatorTree {
+    /// Immediate dominator of `block`, `None` for the entry and for
+    /// unreachable blocks.
+    pub fn idom(&self, block: usize) -> Option<usize> {
+        self.idom[block].filter(|idom| *idom != block)
+    }
+
+    pub fn is_reachable(&self, block: usize) -> bool {
+        self.idom[block].is_some()
+    }
+
+    /// Whether every path from the entry to `b` goes through `a`.
+    pub fn dominates(&self, a: usize, mut b: usize) -> bool {
+        if !self.is_reachable(b) {
+            return false;
+        }
+        loop {
+            if a == b {
+                return true;
+            }
+            match self.idom(b) {
+                Some(idom) => b = idom,
+                None => return false,
+            }
+        }
+    }
+
+    /// Blocks immediately dominated by `block`.
+    pub fn children(&self, block: usize) -> Vec<usize> {
+        (0..self.idom.len())
+            .filter(|b| self.idom(*b) == Some(block))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disass::{CatchType, Handler, TryBlock};
+
+    // 0: const/4 v0, 0
+    // 1: if-eqz v0, +5
+    // 3: add-int/lit8 v0, v0, 1
+    // 5: goto -4
+    // 6: return v0
+    const LOOP: [u8; 14] = [
+        0x12, 0x00, 0x38, 0x00, 0x05, 0x00, 0xd8, 0x00, 0x00, 0x01, 0x28, 0xfc, 0x0f, 0x00,
+    ];
+
+    #[test]
+    fn test_blocks() {
+        let cfg = ControlFlowGraph::build(&LOOP, &ExceptionTable::default()).unwrap();
+        let starts: Vec<(usize, usize)> = cfg.blocks().iter().map(|b| (b.start, b.end)).collect();
+        assert_eq!(starts, vec![(0, 1), (1, 3), (3, 6), (6, 7)]);
+        assert_eq!(cfg.block_at(5), Some(2));
+        let succ: Vec<usize> = cfg.successors(1).iter().map(|e| e.block).collect();
+        assert_eq!(succ, vec![2, 3]);
+        let pred: Vec<usize> = cfg.predecessors(1).iter().map(|e| e.block).collect();
+        assert_eq!(pred, vec![0, 2]);
+        assert_eq!(cfg.reverse_post_order(), vec![0, 1, 3, 2]);
+    }
+
+    #[test]
+    fn test_dominators() {
+        let cfg = ControlFlowGraph::build(&LOOP, &ExceptionTable::default()).unwrap();
+        let dom = cfg.dominators();
+        assert_eq!(dom.idom(0), None);
+        assert_eq!(dom.idom(1), Some(0));
+        assert_eq!(dom.idom(2), Some(1));
+        assert_eq!(dom.idom(3), Some(1));
+        assert!(dom.dominates(1, 2));
+        assert!(!dom.dominates(2, 3));
+        assert_eq!(dom.children(1), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_exceptional_edges() {
+        // 0: invoke-static {}, meth@0
+        // 3: return-void
+        // 4: move-exception v0
+        // 5: return-void
+        let code = [
+            0x71, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0e, 0x00, 0x0d, 0x00, 0x0e, 0x00,
+        ];
+        let tries = ExceptionTable::new(vec![TryBlock {
+            start: 0,
+            end: 3,
+            handlers: vec![Handler {
+                catch: CatchType::All,
+                addr: 4,
+            }],
+        }]);
+        let cfg = ControlFlowGraph::build(&code, &tries).unwrap();
+        let starts: Vec<usize> = cfg.blocks().iter().map(|b| b.start).collect();
+        assert_eq!(starts, vec![0, 3, 4]);
+        assert_eq!(
+            cfg.successors(0),
+            &[
+                Edge {
+                    block: 1,
+                    kind: EdgeKind::Normal
+                },
+                Edge {
+                    block: 2,
+                    kind: EdgeKind::Exceptional
+                }
+            ]
+        );
+        let dom = cfg.dominators();
+        assert_eq!(dom.idom(2), Some(0));
+        assert!(dom.is_reachable(2));
+        assert_eq!(
+            cfg.handler_blocks().into_iter().collect::<Vec<_>>(),
+            vec![2]
+        );
+    }
+}
@@ -0,0 +1,648 @@
+use crate::api::FrameworkApis;
+use crate::apk::{Apk, DexLoadError};
+use crate::features::{package_features, package_features_partial, ApiVector, PackageFeature};
+use crate::similarity::{Matcher, Metric, VectorIndex};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Version of the on-disk format written by `Database::write_to`.
+pub const DATABASE_VERSION: u32 = 4;
+
+/// Label of the versions that no reference build was found for.
+pub const UNKNOWN_VERSION: &str = "unknown";
+
+const MAGIC: &str = "libradar-db";
+
+const API_WEIGHT: f64 = 0.5;
+const CLASS_WEIGHT: f64 = 0.3;
+const METHOD_WEIGHT: f64 = 0.2;
+
+/// Fingerprint of one version of a library: a package whose API usage was
+/// found unchanged in `apps` different applications.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LibraryVersion {
+    pub version: String,
+    pub apps: usize,
+    pub classes: usize,
+    pub methods: usize,
+    /// Class names relative to the library package, e.g. `internal/Util;`.
+    pub class_names: BTreeSet<String>,
+    pub api_calls: ApiVector,
+}
+
+/// A third-party library and the fingerprints of its known versions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Library {
+    pub name: String,
+    pub versions: Vec<LibraryVersion>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Database {
+    /// `FrameworkApis::stable_hash` of the API list used for training.
+    pub api_list: u64,
+    pub libraries: Vec<Library>,
+}
+
+fn ratio(a: usize, b: usize) -> f64 {
+    if a == b {
+        1.0
+    } else {
+        a.min(b) as f64 / a.max(b) as f64
+    }
+}
+
+fn class_overlap(a: &BTreeSet<String>, b: &BTreeSet<String>) -> f64 {
+    ratio(a.intersection(b).count(), a.union(b).count())
+}
+
+impl LibraryVersion {
+    /// Scores in `[0, 1]` how close a package is to this version, mixing the
+    /// overlap of their API multisets, of their class names and the ratio of
+    /// their method counts. Class names only help on unobfuscated code.
+    pub fn similarity(&self, feature: &PackageFeature) -> f64 {
+        API_WEIGHT * Metric::WeightedJaccard.similarity(&self.api_calls, &feature.api_calls)
+            + CLASS_WEIGHT * class_overlap(&self.class_names, &feature.class_names)
+            + METHOD_WEIGHT * ratio(self.methods, feature.methods)
+    }
+}
+
+impl Library {
+    /// Number of sightings of the library, summed over its versions. An app
+    /// seen with several versions is counted once per version.
+    pub fn apps(&self) -> usize {
+        self.versions.iter().map(|v| v.apps).sum()
+    }
+
+    /// The version closest to `feature` and its similarity score.
+    pub fn closest_version(&self, feature: &PackageFeature) -> Option<(&LibraryVersion, f64)> {
+        self.versions
+            .iter()
+            .map(|v| (v, v.similarity(feature)))
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+    }
+}
+
+#[derive(Debug)]
+struct DatabaseFormatError {
+    line: usize,
+    reason: String,
+}
+
+impl fmt::Display for DatabaseFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "Malformed database at line {}: {}",
+            self.line, self.reason
+        ))
+    }
+}
+
+impl std::error::Error for DatabaseFormatError {}
+
+#[derive(Debug)]
+struct MissingLibraryPackage {
+    package: String,
+}
+
+impl fmt::Display for MissingLibraryPackage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "The reference build has no package {}",
+            self.package
+        ))
+    }
+}
+
+impl std::error::Error for MissingLibraryPackage {}
+
+fn format_error(line: usize, reason: &str) -> Box<dyn std::error::Error> {
+    Box::new(DatabaseFormatError {
+        line,
+        reason: String::from(reason),
+    })
+}
+
+fn parse_field<T: std::str::FromStr>(
+    field: Option<&str>,
+    line: usize,
+) -> Result<T, Box<dyn std::error::Error>> {
+    field
+        .and_then(|f| f.parse().ok())
+        .ok_or_else(|| format_error(line, "missing or invalid number"))
+}
+
+impl Database {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        Database::read_from(BufReader::new(fs::File::open(path)?))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let mut w = BufWriter::new(fs::File::create(path)?);
+        self.write_to(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Writes the database as tab separated text. The first line holds the
+    /// format version and the hash of the API list, then every library is a
+    /// `lib` line followed by one `ver` line per version. A version line ends
+    /// with the stable hash and the compact form of its API vector, and its
+    /// comma separated classes.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<(), Box<dyn std::error::Error>> {
+        writeln!(w, "{}\t{}\t{:016x}", MAGIC, DATABASE_VERSION, self.api_list)?;
+        for lib in &self.libraries {
+            writeln!(w, "lib\t{}", lib.name)?;
+            for v in &lib.versions {
+                writeln!(
+                    w,
+                    "ver\t{}\t{}\t{}\t{}\t{:016x}\t{}\t{}",
+                    v.version,
+                    v.apps,
+                    v.classes,
+                    v.methods,
+                    v.api_calls.stable_hash(),
+                    v.api_calls.to_compact(),
+                    v.class_names
+                        .iter()
+                        .map(|c| c.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(",")
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: BufRead>(r: R) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut db = Database::default();
+        let mut lines = r.lines().enumerate();
+
+        match lines.next() {
+            Some((_, header)) => {
+                let header = header?;
+                let mut fields = header.split('\t');
+                if fields.next() != Some(MAGIC) {
+                    return Err(format_error(1, "not a libradar database"));
+                }
+                let version: u32 = parse_field(fields.next(), 1)?;
+                if version != DATABASE_VERSION {
+                    return Err(format_error(1, "unsupported database version"));
+                }
+                db.api_list = fields
+                    .next()
+                    .and_then(|h| u64::from_str_radix(h, 16).ok())
+                    .ok_or_else(|| format_error(1, "missing or invalid api list hash"))?;
+            }
+            None => return Err(format_error(1, "empty file")),
+        }
+
+        for (i, line) in lines {
+            let line = line?;
+            let n = i + 1;
+            let mut fields = line.split('\t');
+            match fields.next() {
+                Some("lib") => {
+                    let name = fields
+                        .next()
+                        .ok_or_else(|| format_error(n, "missing library name"))?;
+                    db.libraries.push(Library {
+                        name: String::from(name),
+                        versions: Vec::new(),
+                    });
+                }
+                Some("ver") => {
+                    let version = fields
+                        .next()
+                        .ok_or_else(|| format_error(n, "missing version name"))?;
+                    let apps = parse_field(fields.next(), n)?;
+                    let classes = parse_field(fields.next(), n)?;
+                    let methods = parse_field(fields.next(), n)?;
+                    let hash = fields
+                        .next()
+                        .and_then(|h| u64::from_str_radix(h, 16).ok())
+                        .ok_or_else(|| format_error(n, "missing or invalid hash"))?;
+                    let api_calls = ApiVector::from_compact(fields.next().unwrap_or(""))?;
+                    if api_calls.stable_hash() != hash {
+                        return Err(format_error(n, "hash does not match the api vector"));
+                    }
+                    let class_names = fields
+                        .next()
+                        .unwrap_or("")
+                        .split(',')
+                        .filter(|c| !c.is_empty())
+                        .map(String::from)
+                        .collect();
+                    let lib = db
+                        .libraries
+                        .last_mut()
+                        .ok_or_else(|| format_error(n, "version outside of a library"))?;
+                    lib.versions.push(LibraryVersion {
+                        version: String::from(version),
+                        apps,
+                        classes,
+                        methods,
+                        class_names,
+                        api_calls,
+                    });
+                }
+                Some("") => {}
+                _ => return Err(format_error(n, "unknown record")),
+            }
+        }
+        Ok(db)
+    }
+}
+
+struct Candidate {
+    apps: HashSet<String>,
+    names: HashMap<String, usize>,
+    versions: BTreeSet<String>,
+    classes: usize,
+    methods: usize,
+    class_names: BTreeSet<String>,
+}
+
+impl Candidate {
+    fn absorb(&mut self, other: Candidate) {
+        if self.versions.is_empty() && !other.versions.is_empty() {
+            self.classes = other.classes;
+            self.methods = other.methods;
+            self.class_names = other.class_names;
+        }
+        self.apps.extend(other.apps);
+        self.versions.extend(other.versions);
+        for (name, count) in other.names {
+            *self.names.entry(name).or_insert(0) += count;
+        }
+    }
+}
+
+/// Groups the package features of a corpus of apps. Packages with the same
+/// API usage in at least `min_apps` distinct apps become library versions,
+/// and the versions found under the same package name form a library.
+pub struct Trainer {
+    min_apps: usize,
+    apis: FrameworkApis,
+    matcher: Matcher,
+    partial: bool,
+    candidates: HashMap<ApiVector, Candidate>,
+}
+
+impl Trainer {
+    pub fn new(min_apps: usize, apis: FrameworkApis) -> Self {
+        Trainer {
+            min_apps,
+            apis,
+            matcher: Matcher::default(),
+            partial: false,
+            candidates: HashMap::new(),
+        }
+    }
+
+    /// Clusters the packages whose API vectors are similar according to
+    /// `matcher` instead of requiring identical vectors. Each cluster is
+    /// represented by its member seen in the most apps.
+    pub fn with_matcher(mut self, matcher: Matcher) -> Self {
+        self.matcher = matcher;
+        self
+    }
+
+    /// Trains on the dex files of an apk that can be loaded instead of
+    /// skipping the whole apk when one of them is broken.
+    pub fn with_partial_dex(mut self, partial: bool) -> Self {
+        self.partial = partial;
+        self
+    }
+
+    fn features(
+        &self,
+        apk: &Apk,
+    ) -> Result<(Vec<PackageFeature>, Vec<DexLoadError>), Box<dyn std::error::Error>> {
+        if self.partial {
+            package_features_partial(apk, &self.apis)
+        } else {
+            Ok((package_features(apk, &self.apis)?, Vec::new()))
+        }
+    }
+
+    /// Returns the errors of the dex files skipped with `with_partial_dex`.
+    pub fn add_apk(&mut self, apk: &Apk) -> Result<Vec<DexLoadError>, Box<dyn std::error::Error>> {
+        let (features, errors) = self.features(apk)?;
+        for feature in features {
+            self.add_feature(&apk.path, feature, None);
+        }
+        Ok(errors)
+    }
+
+    /// Adds a reference build of a known library version. The packages under
+    /// `library`, a package name such as `com.squareup.okhttp3`, are labelled
+    /// with `version`, which names the database entries that match them. The
+    /// other packages, e.g. bundled dependencies, are added unlabelled.
+    pub fn add_reference(
+        &mut self,
+        apk: &Apk,
+        library: &str,
+        version: &str,
+    ) -> Result<Vec<DexLoadError>, Box<dyn std::error::Error>> {
+        let (features, errors) = self.features(apk)?;
+        self.add_reference_features(&apk.path, features, library, version)?;
+        Ok(errors)
+    }
+
+    pub fn add_reference_features(
+        &mut self,
+        app: &str,
+        features: Vec<PackageFeature>,
+        library: &str,
+        version: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let package = format!("L{}/", library.replace('.', "/"));
+        if !features.iter().any(|f| f.package.starts_with(&package)) {
+            return Err(Box::new(MissingLibraryPackage { package }));
+        }
+        for feature in features {
+            let label = if feature.package.starts_with(&package) {
+                Some(version)
+            } else {
+                None
+            };
+            self.add_feature(app, feature, label);
+        }
+        Ok(())
+    }
+
+    pub fn add_feature(&mut self, app: &str, feature: PackageFeature, version: Option<&str>) {
+        if feature.api_calls.is_empty() {
+            return;
+        }
+        let candidate = self
+            .candidates
+            .entry(feature.api_calls)
+            .or_insert_with(|| Candidate {
+                apps: HashSet::new(),
+                names: HashMap::new(),
+                versions: BTreeSet::new(),
+                classes: 0,
+                methods: 0,
+                class_names: BTreeSet::new(),
+            });
+        // Reference builds are not obfuscated, so prefer their class names.
+        if candidate.apps.is_empty() || (version.is_some() && candidate.versions.is_empty()) {
+            candidate.classes = feature.classes;
+            candidate.methods = feature.methods;
+            candidate.class_names = feature.class_names;
+        }
+        if let Some(version) = version {
+            candidate.versions.insert(String::from(version));
+        }
+        candidate.apps.insert(String::from(app));
+        *candidate.names.entry(feature.package).or_insert(0) += 1;
+    }
+
+    /// Builds the database from the candidates seen so far. Each candidate is
+    /// filed under the package name it was most often found with. Candidates
+    /// matched by several reference builds get their labels joined by `/`.
+    pub fn into_database(self) -> Database {
+        let min_apps = self.min_apps;
+        let matcher = self.matcher;
+        let mut candidates: Vec<(ApiVector, Candidate)> = self.candidates.into_iter().collect();
+        candidates.sort_by(|a, b| {
+            b.1.apps
+                .len()
+                .cmp(&a.1.apps.len())
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        if !matcher.is_exact() {
+            let mut clusters: Vec<(ApiVector, Candidate)> = Vec::new();
+            let mut index = VectorIndex::default();
+            for (api_calls, c) in candidates {
+                let cluster = index
+                    .candidates(&api_calls)
+                    .into_iter()
+                    .find(|&i| matcher.matches(&clusters[i].0, &api_calls).is_some());
+                match cluster {
+                    Some(i) => clusters[i].1.absorb(c),
+                    None => {
+                        index.insert(&api_calls);
+                        clusters.push((api_calls, c));
+                    }
+                }
+            }
+            candidates = clusters;
+        }
+
+        let mut libraries: BTreeMap<String, Vec<LibraryVersion>> = BTreeMap::new();
+        for (api_calls, c) in candidates {
+            if c.apps.len() < min_apps {
+                continue;
+            }
+            let name = c
+                .names
+                .iter()
+                .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+                .map(|(name, _)| name.clone())
+                .unwrap_or_default();
+            let version = if c.versions.is_empty() {
+                String::from(UNKNOWN_VERSION)
+            } else {
+                c.versions.into_iter().collect::<Vec<String>>().join("/")
+            };
+            libraries.entry(name).or_default().push(LibraryVersion {
+                version,
+                apps: c.apps.len(),
+                classes: c.classes,
+                methods: c.methods,
+                class_names: c.class_names,
+                api_calls,
+            });
+        }
+
+        let mut libraries: Vec<Library> = libraries
+            .into_iter()
+            .map(|(name, mut versions)| {
+                versions.sort_by(|a, b| {
+                    a.methods
+                        .cmp(&b.methods)
+                        .then_with(|| a.version.cmp(&b.version))
+                });
+                Library { name, versions }
+            })
+            .collect();
+        libraries.sort_by(|a, b| b.apps().cmp(&a.apps()).then_with(|| a.name.cmp(&b.name)));
+        Database {
+            api_list: self.apis.stable_hash(),
+            libraries,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feature(package: &str, classes: &[&str], apis: &[(&str, usize)]) -> PackageFeature {
+        let mut api_calls = ApiVector::new();
+        for (api, count) in apis {
+            api_calls.add(api, *count);
+        }
+        PackageFeature {
+            package: String::from(package),
+            classes: classes.len(),
+            methods: classes.len() * 2,
+            class_names: classes.iter().map(|c| String::from(*c)).collect(),
+            api_calls,
+        }
+    }
+
+    #[test]
+    fn test_trainer_min_apps() {
+        let apis = [("Ljava/lang/String;->equals", 2)];
+        let mut trainer = Trainer::new(2, FrameworkApis::default());
+        trainer.add_feature("a.apk", feature("Lcom/lib/", &["A;"], &apis), None);
+        trainer.add_feature("b.apk", feature("Lcom/lib/", &["A;"], &apis), None);
+        trainer.add_feature("c.apk", feature("La/b/", &["a;"], &apis), None);
+        let app = feature("Lcom/app/", &["Main;"], &[("Ljava/io/File;->delete", 1)]);
+        trainer.add_feature("a.apk", app, None);
+        let db = trainer.into_database();
+        assert_eq!(db.libraries.len(), 1);
+        assert_eq!(db.libraries[0].name, "Lcom/lib/");
+        assert_eq!(db.libraries[0].versions.len(), 1);
+        assert_eq!(db.libraries[0].versions[0].apps, 3);
+        assert_eq!(db.libraries[0].versions[0].version, UNKNOWN_VERSION);
+    }
+
+    #[test]
+    fn test_trainer_versions() {
+        let v1 = [("Ljava/net/Socket;->connect", 1)];
+        let v2 = [
+            ("Ljava/net/Socket;->connect", 1),
+            ("Ljava/net/Socket;->close", 1),
+        ];
+        let mut trainer = Trainer::new(2, FrameworkApis::default());
+        trainer.add_feature("a.apk", feature("Lokhttp3/", &["a;"], &v1), None);
+        trainer.add_feature(
+            "ref1.apk",
+            feature("Lokhttp3/", &["Call;"], &v1),
+            Some("3.0"),
+        );
+        trainer.add_feature("b.apk", feature("Lokhttp3/", &["a;", "b;"], &v2), None);
+        trainer.add_feature(
+            "ref2.apk",
+            feature("Lokhttp3/", &["Call;", "Util;"], &v2),
+            Some("3.1"),
+        );
+        let db = trainer.into_database();
+        assert_eq!(db.libraries.len(), 1);
+        let lib = &db.libraries[0];
+        let versions: Vec<&str> = lib.versions.iter().map(|v| v.version.as_str()).collect();
+        assert_eq!(versions, ["3.0", "3.1"]);
+        assert!(lib.versions[1].class_names.contains("Util;"));
+
+        let probe = feature("Lx/", &["Call;", "Util;"], &v2);
+        let (closest, score) = lib.closest_version(&probe).expect("no version");
+        assert_eq!(closest.version, "3.1");
+        assert!((score - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_reference_subtree() {
+        let lib = [("Ljava/net/Socket;->connect", 1)];
+        let dep = [("Ljava/io/File;->delete", 1)];
+        let mut trainer = Trainer::new(1, FrameworkApis::default());
+        let features = vec![
+            feature(
+                "Lcom/",
+                &["squareup/okhttp3/Call;", "Dep;"],
+                &[("Ljava/lang/Object;->wait", 1)],
+            ),
+            feature("Lcom/squareup/okhttp3/", &["Call;"], &lib),
+            feature("Lokio/", &["Buffer;"], &dep),
+        ];
+        trainer
+            .add_reference_features(
+                "ref.apk",
+                features.clone(),
+                "com.squareup.okhttp3",
+                "3.12.0",
+            )
+            .unwrap();
+        assert!(trainer
+            .add_reference_features("ref.apk", features, "okhttp", "3.12.0")
+            .is_err());
+        let db = trainer.into_database();
+        let versions: Vec<(&str, &str)> = db
+            .libraries
+            .iter()
+            .map(|l| (l.name.as_str(), l.versions[0].version.as_str()))
+            .collect();
+        assert_eq!(
+            versions,
+            [
+                ("Lcom/", UNKNOWN_VERSION),
+                ("Lcom/squareup/okhttp3/", "3.12.0"),
+                ("Lokio/", UNKNOWN_VERSION)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trainer_clustering() {
+        let full = [
+            ("Ljava/net/Socket;->connect", 9),
+            ("Ljava/net/Socket;->close", 1),
+        ];
+        let shrunk = [("Ljava/net/Socket;->connect", 9)];
+        let mut trainer = Trainer::new(2, FrameworkApis::default());
+        trainer.add_feature("a.apk", feature("Lokhttp3/", &["a;"], &full), None);
+        trainer.add_feature("b.apk", feature("Lokhttp3/", &["a;"], &shrunk), None);
+        assert!(trainer.into_database().libraries.is_empty());
+
+        let mut trainer = Trainer::new(2, FrameworkApis::default())
+            .with_matcher(Matcher::new(Metric::WeightedJaccard, 0.8));
+        trainer.add_feature("a.apk", feature("Lokhttp3/", &["a;"], &full), None);
+        trainer.add_feature("b.apk", feature("Lokhttp3/", &["a;"], &shrunk), None);
+        trainer.add_feature("c.apk", feature("Lokhttp3/", &["a;"], &full), None);
+        let db = trainer.into_database();
+        assert_eq!(db.libraries.len(), 1);
+        assert_eq!(db.libraries[0].versions.len(), 1);
+        assert_eq!(db.libraries[0].versions[0].apps, 3);
+        assert_eq!(
+            db.libraries[0].versions[0]
+                .api_calls
+                .get("Ljava/net/Socket;->close"),
+            1
+        );
+    }
+
+    #[test]
+    fn test_write_and_read() -> Result<(), Box<dyn std::error::Error>> {
+        let mut trainer = Trainer::new(1, FrameworkApis::default());
+        let apis = [("Ljava/io/File;->delete", 3)];
+        trainer.add_feature(
+            "a.apk",
+            feature("Lcom/lib/", &["A;", "b/B;"], &apis),
+            Some("1.0"),
+        );
+        let db = trainer.into_database();
+        let mut buf: Vec<u8> = Vec::new();
+        db.write_to(&mut buf)?;
+        assert_eq!(Database::read_from(&buf[..])?, db);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_bad_hash() {
+        let data = "libradar-db\t4\t0000000000000000\nlib\tLcom/lib/\nver\t1.0\t2\t1\t1\t0000000000000000\tLjava/io/File;->delete=1\tA;\n";
+        assert!(Database::read_from(data.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_read_bad_version() {
+        let data = "libradar-db\t999\n";
+        assert!(Database::read_from(data.as_bytes()).is_err());
+    }
+}
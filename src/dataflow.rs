@@ -0,0 +1,543 @@
+use crate::apk::DexFile;
+use crate::cfg::{ControlFlowGraph, EdgeKind};
+use crate::disass::{
+    code_bytes, BinaryOp, DecodedInst, DisassError, ExceptionTable, Inst, InstIterator, NumType,
+    Reg, Resolver,
+};
+use crate::hierarchy::class_descriptor;
+use crate::smali::DexResolver;
+use dex::code::CodeItem;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+const STRING_BUILDER: &str = "Ljava/lang/StringBuilder;";
+
+/// Abstract value of a register.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Value {
+    /// No definition reaches the register.
+    Undefined,
+    Int(i64),
+    Str(String),
+    /// Class object loaded with `const-class`, as a type descriptor.
+    Class(String),
+    /// `StringBuilder` with known content.
+    Builder(String),
+    /// `Method` or `Constructor` object looked up by name with reflection.
+    Method {
+        class: String,
+        name: String,
+    },
+    /// Not a constant, such as a parameter of the method.
+    Unknown,
+}
+
+impl Value {
+    fn join(&self, other: &Value) -> Value {
+        match (self, other) {
+            (Value::Undefined, v) | (v, Value::Undefined) => v.clone(),
+            (a, b) if a == b => a.clone(),
+            _ => Value::Unknown,
+        }
+    }
+
+    /// String content of a string or builder value.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) | Value::Builder(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Pool lookups needed by the analysis.
+pub trait ConstantPool {
+    fn string(&self, idx: u32) -> Option<String>;
+    fn type_descriptor(&self, idx: u32) -> Option<String>;
+    /// Method in the `Lclass;->name(params)return` form.
+    fn method(&self, idx: u32) -> Option<String>;
+}
+
+impl ConstantPool for DexFile {
+    fn string(&self, idx: u32) -> Option<String> {
+        self.get_string(idx).ok().map(|s| s.to_string())
+    }
+
+    fn type_descriptor(&self, idx: u32) -> Option<String> {
+        Resolver::type_descriptor(&DexResolver::new(self), idx.into())
+    }
+
+    fn method(&self, idx: u32) -> Option<String> {
+        Resolver::method(&DexResolver::new(self), idx.into())
+    }
+}
+
+/// Values of the arguments of the invoke at `addr`, one per argument
+/// register.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvokeArguments {
+    pub addr: usize,
+    pub method_idx: u32,
+    pub method: Option<String>,
+    pub args: Vec<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+struct State {
+    regs: BTreeMap<Reg, Value>,
+    /// Value that the next `move-result` will read.
+    result: Option<Value>,
+}
+
+impl State {
+    fn get(&self, reg: Reg) -> Value {
+        self.regs.get(&reg).cloned().unwrap_or(Value::Undefined)
+    }
+
+    fn set(&mut self, reg: Reg, value: Value) {
+        self.regs.insert(reg, value);
+    }
+
+    fn join(&self, other: &State) -> State {
+        let mut regs = self.regs.clone();
+        for (reg, value) in &other.regs {
+            let joined = self.get(*reg).join(value);
+            regs.insert(*reg, joined);
+        }
+        let result = match (&self.result, &other.result) {
+            (Some(a), Some(b)) => Some(a.join(b)),
+            (a, b) => a.clone().or_else(|| b.clone()),
+        };
+        State { regs, result }
+    }
+}
+
+fn fold(op: BinaryOp, a: i64, b: i64) -> Option<i64> {
+    let (a, b) = (a as i32, b as i32);
+    let value = match op {
+        BinaryOp::Add => a.wrapping_add(b),
+        BinaryOp::Sub => a.wrapping_sub(b),
+        BinaryOp::Rsub => b.wrapping_sub(a),
+        BinaryOp::Mul => a.wrapping_mul(b),
+        BinaryOp::Div => a.checked_div(b)?,
+        BinaryOp::Rem => a.checked_rem(b)?,
+        BinaryOp::And => a & b,
+        BinaryOp::Or => a | b,
+        BinaryOp::Xor => a ^ b,
+        BinaryOp::Shl => a.wrapping_shl(b as u32),
+        BinaryOp::Shr => a.wrapping_shr(b as u32),
+        BinaryOp::Ushr => (a as u32).wrapping_shr(b as u32) as i32,
+    };
+    Some(value.into())
+}
+
+/// Forward propagation of constant ints, strings, classes and
+/// `StringBuilder` contents through the registers of a method.
+pub struct ConstantPropagation {
+    invokes: Vec<InvokeArguments>,
+}
+
+impl ConstantPropagation {
+    pub fn from_code(code: &CodeItem, dex: &DexFile) -> Result<Self, DisassError> {
+        // The arguments are passed in the last registers of the frame
+        let registers = code.registers_size();
+        let parameters = registers.saturating_sub(code.ins_size())..registers;
+        Self::run(
+            code_bytes(code),
+            &ExceptionTable::from_code(code),
+            parameters,
+            dex,
+        )
+    }
+
+    /// Runs the analysis with the `parameters` registers holding unknown
+    /// values on entry.
+    pub fn run(
+        code: &[u8],
+        tries: &ExceptionTable,
+        parameters: Range<Reg>,
+        pool: &dyn ConstantPool,
+    ) -> Result<Self, DisassError> {
+        let cfg = ControlFlowGraph::build(code, tries)?;
+        let insts: HashMap<usize, Inst> = InstIterator::new(code, code.len())
+            .map(|inst| inst.map(|inst| (inst.addr(), inst)))
+            .collect::<Result<_, _>>()?;
+        let order = cfg.reverse_post_order();
+
+        let mut entry: Vec<State> = vec![State::default(); cfg.blocks().len()];
+        if let Some(state) = entry.first_mut() {
+            for reg in parameters {
+                state.set(reg, Value::Unknown);
+            }
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &order {
+                let mut state = entry[block].clone();
+                for addr in &cfg.block(block).insts {
+                    transfer(&insts[addr], &mut state, pool, None);
+                }
+                for edge in cfg.successors(block) {
+                    // The exception may be thrown before or after any
+                    // definition of the block
+                    let out = match edge.kind {
+                        EdgeKind::Normal => state.clone(),
+                        EdgeKind::Exceptional => State {
+                            result: None,
+                            ..entry[block].join(&state)
+                        },
+                    };
+                    let joined = entry[edge.block].join(&out);
+                    if joined != entry[edge.block] {
+                        entry[edge.block] = joined;
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        let mut invokes = Vec::new();
+        for &block in &order {
+            let mut state = entry[block].clone();
+            for addr in &cfg.block(block).insts {
+                transfer(&insts[addr], &mut state, pool, Some(&mut invokes));
+            }
+        }
+        invokes.sort_by_key(|invoke| invoke.addr);
+        Ok(ConstantPropagation { invokes })
+    }
+
+    /// Argument values at every invoke reachable from the entry.
+    pub fn invokes(&self) -> &[InvokeArguments] {
+        &self.invokes
+    }
+
+    pub fn invoke_at(&self, addr: usize) -> Option<&InvokeArguments> {
+        self.invokes.iter().find(|invoke| invoke.addr == addr)
+    }
+}
+
+/// Effect of a call on the receiver and the value it returns.
+fn call(method: &str, args: &[Value]) -> (Option<Value>, Value) {
+    match method.strip_prefix(STRING_BUILDER) {
+        Some(name) => string_builder_call(name, args),
+        None => (None, reflection_call(method, args)),
+    }
+}
+
+fn string_builder_call(name: &str, args: &[Value]) -> (Option<Value>, Value) {
+    let receiver = args.first().and_then(Value::as_str).map(String::from);
+    let arg = args.get(1);
+    match (name, receiver, arg) {
+        ("-><init>()V", _, _) => (Some(Value::Builder(String::new())), Value::Unknown),
+        ("-><init>(Ljava/lang/String;)V", _, Some(Value::Str(s))) => {
+            (Some(Value::Builder(s.clone())), Value::Unknown)
+        }
+        (m, Some(mut content), Some(arg)) if m.starts_with("->append(") => {
+            let appended = match (m, arg) {
+                ("->append(Ljava/lang/String;)Ljava/lang/StringBuilder;", Value::Str(s)) => {
+                    Some(s.clone())
+                }
+                ("->append(I)Ljava/lang/StringBuilder;", Value::Int(i)) => Some(i.to_string()),
+                ("->append(C)Ljava/lang/StringBuilder;", Value::Int(c)) => {
+                    char::from_u32(*c as u32).map(String::from)
+                }
+                _ => None,
+            };
+            match appended {
+                Some(s) => {
+                    content.push_str(&s);
+                    (
+                        Some(Value::Builder(content.clone())),
+                        Value::Builder(content),
+                    )
+                }
+                None => (Some(Value::Unknown), Value::Unknown),
+            }
+        }
+        ("->toString()Ljava/lang/String;", Some(content), _) => (None, Value::Str(content)),
+        _ => (Some(Value::Unknown), Value::Unknown),
+    }
+}
+
+/// Value returned by the reflection APIs that look up classes, methods and
+/// constructors by name.
+fn reflection_call(method: &str, args: &[Value]) -> Value {
+    let (class, name) = match method.split_once("->") {
+        Some(parts) => parts,
+        None => return Value::Unknown,
+    };
+    match (class, name, args) {
+        (
+            "Ljava/lang/Class;",
+            "forName(Ljava/lang/String;)Ljava/lang/Class;"
+            | "forName(Ljava/lang/String;ZLjava/lang/ClassLoader;)Ljava/lang/Class;",
+            [Value::Str(name), ..],
+        )
+        | (_, "loadClass(Ljava/lang/String;)Ljava/lang/Class;", [_, Value::Str(name)]) => {
+            Value::Class(class_descriptor(name))
+        }
+        (
+            "Ljava/lang/Class;",
+            "getMethod(Ljava/lang/String;[Ljava/lang/Class;)Ljava/lang/reflect/Method;"
+            | "getDeclaredMethod(Ljava/lang/String;[Ljava/lang/Class;)Ljava/lang/reflect/Method;",
+            [Value::Class(class), Value::Str(name), ..],
+        ) => Value::Method {
+            class: class.clone(),
+            name: name.clone(),
+        },
+        (
+            "Ljava/lang/Class;",
+            "getConstructor([Ljava/lang/Class;)Ljava/lang/reflect/Constructor;"
+            | "getDeclaredConstructor([Ljava/lang/Class;)Ljava/lang/reflect/Constructor;",
+            [Value::Class(class), ..],
+        ) => Value::Method {
+            class: class.clone(),
+            name: String::from("<init>"),
+        },
+        _ => Value::Unknown,
+    }
+}
+
+fn transfer(
+    inst: &Inst,
+    state: &mut State,
+    pool: &dyn ConstantPool,
+    invokes: Option<&mut Vec<InvokeArguments>>,
+) {
+    let result = state.result.take();
+    match inst.decode() {
+        DecodedInst::Const { dst, value, wide } => {
+            state.set(dst, Value::Int(value));
+            if let Some(high) = dst.checked_add(1).filter(|_| wide) {
+                state.set(high, Value::Unknown);
+            }
+        }
+        DecodedInst::ConstString { dst, string_idx } => {
+            let value = pool.string(string_idx).map_or(Value::Unknown, Value::Str);
+            state.set(dst, value);
+        }
+        DecodedInst::ConstClass { dst, type_idx } => {
+            let value = pool
+                .type_descriptor(type_idx)
+                .map_or(Value::Unknown, Value::Class);
+            state.set(dst, value);
+        }
+        DecodedInst::Move { .. } => {
+            let values: Vec<Value> = inst.uses().into_iter().map(|reg| state.get(reg)).collect();
+            for (reg, value) in inst.defs().into_iter().zip(values) {
+                state.set(reg, value);
+            }
+        }
+        DecodedInst::MoveResult { dst, .. } => {
+            for reg in inst.defs() {
+                state.set(reg, Value::Unknown);
+            }
+            state.set(dst, result.unwrap_or(Value::Unknown));
+        }
+        DecodedInst::NewInstance { dst, type_idx } => {
+            let value = match pool.type_descriptor(type_idx) {
+                Some(name) if name == STRING_BUILDER => Value::Builder(String::new()),
+                _ => Value::Unknown,
+            };
+            state.set(dst, value);
+        }
+        DecodedInst::Invoke {
+            method_idx, args, ..
+        } => {
+            let values: Vec<Value> = args.iter().map(|reg| state.get(*reg)).collect();
+            let method = pool.method(method_idx);
+            let (receiver, returned) = match &method {
+                Some(method) => call(method, &values),
+                None => (None, Value::Unknown),
+            };
+            if let (Some(receiver), Some(reg)) = (receiver, args.first()) {
+                state.set(*reg, receiver);
+            }
+            state.result = Some(returned);
+            if let Some(invokes) = invokes {
+                invokes.push(InvokeArguments {
+                    addr: inst.addr(),
+                    method_idx,
+                    method,
+                    args: values,
+                });
+            }
+        }
+        DecodedInst::BinaryLiteral {
+            op,
+            dst,
+            src,
+            literal,
+        } => {
+            let value = match state.get(src) {
+                Value::Int(a) => fold(op, a, literal.into()).map_or(Value::Unknown, Value::Int),
+                _ => Value::Unknown,
+            };
+            state.set(dst, value);
+        }
+        DecodedInst::Binary {
+            op,
+            ty: NumType::Int,
+            dst,
+            a,
+            b,
+        } => {
+            let value = match (state.get(a), state.get(b)) {
+                (Value::Int(a), Value::Int(b)) => fold(op, a, b).map_or(Value::Unknown, Value::Int),
+                _ => Value::Unknown,
+            };
+            state.set(dst, value);
+        }
+        _ => {
+            for reg in inst.defs() {
+                state.set(reg, Value::Unknown);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Pool;
+
+    impl ConstantPool for Pool {
+        fn string(&self, idx: u32) -> Option<String> {
+            ["https://", "example.com", "com.example.Payload", "run"]
+                .get(idx as usize)
+                .map(|s| s.to_string())
+        }
+
+        fn type_descriptor(&self, idx: u32) -> Option<String> {
+            ["Ljava/lang/StringBuilder;", "Ljava/lang/Object;"]
+                .get(idx as usize)
+                .map(|s| s.to_string())
+        }
+
+        fn method(&self, idx: u32) -> Option<String> {
+            [
+                "Ljava/lang/StringBuilder;-><init>()V",
+                "Ljava/lang/StringBuilder;->append(Ljava/lang/String;)Ljava/lang/StringBuilder;",
+                "Ljava/lang/StringBuilder;->toString()Ljava/lang/String;",
+                "Ljava/net/URL;-><init>(Ljava/lang/String;)V",
+                "Ljava/lang/Integer;->valueOf(I)Ljava/lang/Integer;",
+                "Ljava/lang/Class;->forName(Ljava/lang/String;)Ljava/lang/Class;",
+                "Ljava/lang/Class;->getMethod(Ljava/lang/String;[Ljava/lang/Class;)Ljava/lang/reflect/Method;",
+            ]
+            .get(idx as usize)
+            .map(|s| s.to_string())
+        }
+    }
+
+    #[test]
+    fn test_string_builder() {
+        // 0: new-instance v0, type@0
+        // 2: invoke-direct {v0}, meth@0
+        // 5: const-string v1, string@0
+        // 7: invoke-virtual {v0, v1}, meth@1
+        // a: const-string v1, string@1
+        // c: invoke-virtual {v0, v1}, meth@1
+        // f: move-result-object v0
+        // 10: invoke-virtual {v0}, meth@2
+        // 13: move-result-object v2
+        // 14: invoke-direct {v3, v2}, meth@3
+        // 17: return-void
+        let code = [
+            0x22, 0x00, 0x00, 0x00, 0x70, 0x10, 0x00, 0x00, 0x00, 0x00, 0x1a, 0x01, 0x00, 0x00,
+            0x6e, 0x20, 0x01, 0x00, 0x10, 0x00, 0x1a, 0x01, 0x01, 0x00, 0x6e, 0x20, 0x01, 0x00,
+            0x10, 0x00, 0x0c, 0x00, 0x6e, 0x10, 0x02, 0x00, 0x00, 0x00, 0x0c, 0x02, 0x70, 0x20,
+            0x03, 0x00, 0x23, 0x00, 0x0e, 0x00,
+        ];
+        let analysis =
+            ConstantPropagation::run(&code, &ExceptionTable::default(), 0..0, &Pool).unwrap();
+        assert_eq!(analysis.invokes().len(), 5);
+        let url = analysis.invoke_at(0x14).unwrap();
+        assert_eq!(url.method_idx, 3);
+        assert_eq!(
+            url.args,
+            vec![
+                Value::Undefined,
+                Value::Str(String::from("https://example.com"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ints_and_joins() {
+        // 0: const/16 v0, #0x10
+        // 2: xor-int/lit8 v0, v0, #0x3
+        // 4: if-eqz v1, +4
+        // 6: const/4 v2, #1
+        // 7: goto +2
+        // 8: const/4 v2, #2
+        // 9: invoke-static {v0, v2}, meth@4
+        // c: return-void
+        let code = [
+            0x13, 0x00, 0x10, 0x00, 0xdf, 0x00, 0x00, 0x03, 0x38, 0x01, 0x04, 0x00, 0x12, 0x12,
+            0x28, 0x02, 0x12, 0x22, 0x71, 0x20, 0x04, 0x00, 0x20, 0x00, 0x0e, 0x00,
+        ];
+        let analysis =
+            ConstantPropagation::run(&code, &ExceptionTable::default(), 0..0, &Pool).unwrap();
+        let invoke = analysis.invoke_at(9).unwrap();
+        assert_eq!(invoke.args, vec![Value::Int(0x13), Value::Unknown]);
+    }
+
+    #[test]
+    fn test_parameter_join() {
+        // v1 and v2 are parameters
+        // 0: if-eqz v2, +4
+        // 2: const-string v1, string@2
+        // 4: invoke-static {v1}, meth@5
+        // 7: return-void
+        let code = [
+            0x38, 0x02, 0x04, 0x00, 0x1a, 0x01, 0x02, 0x00, 0x71, 0x10, 0x05, 0x00, 0x01, 0x00,
+            0x0e, 0x00,
+        ];
+        let analysis =
+            ConstantPropagation::run(&code, &ExceptionTable::default(), 1..3, &Pool).unwrap();
+        assert_eq!(analysis.invoke_at(4).unwrap().args, vec![Value::Unknown]);
+    }
+
+    #[test]
+    fn test_reflection() {
+        // 0: const-string v0, string@2
+        // 2: invoke-static {v0}, meth@5
+        // 5: move-result-object v0
+        // 6: const-string v1, string@3
+        // 8: const/4 v2, #0
+        // 9: invoke-virtual {v0, v1, v2}, meth@6
+        // c: move-result-object v3
+        // d: invoke-static {v3}, meth@4
+        // 10: return-void
+        let code = [
+            0x1a, 0x00, 0x02, 0x00, 0x71, 0x10, 0x05, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x1a, 0x01,
+            0x03, 0x00, 0x12, 0x02, 0x6e, 0x30, 0x06, 0x00, 0x10, 0x02, 0x0c, 0x03, 0x71, 0x10,
+            0x04, 0x00, 0x03, 0x00, 0x0e, 0x00,
+        ];
+        let analysis =
+            ConstantPropagation::run(&code, &ExceptionTable::default(), 0..0, &Pool).unwrap();
+        let class = String::from("Lcom/example/Payload;");
+        assert_eq!(
+            analysis.invoke_at(9).unwrap().args,
+            vec![
+                Value::Class(class.clone()),
+                Value::Str(String::from("run")),
+                Value::Int(0)
+            ]
+        );
+        assert_eq!(
+            analysis.invoke_at(0xd).unwrap().args,
+            vec![Value::Method {
+                class,
+                name: String::from("run")
+            }]
+        );
+        assert_eq!(
+            class_descriptor("[Ljava.lang.String;"),
+            "[Ljava/lang/String;"
+        );
+    }
+}
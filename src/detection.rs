@@ -0,0 +1,320 @@
+use crate::api::FrameworkApis;
+use crate::apk::{Apk, DexLoadError};
+use crate::database::{Database, Library, LibraryVersion};
+use crate::features::{package_features, package_features_partial, PackageFeature};
+use crate::similarity::{ApiDiff, Matcher, VectorIndex};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A library of the database found in an apk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detection {
+    pub library: String,
+    /// The closest known version of the library.
+    pub version: String,
+    /// How close the package is to `version`, from 0 to 1.
+    pub confidence: f64,
+    pub package: String,
+    /// Similarity of the API vectors of the package and the library.
+    pub similarity: f64,
+    pub classes: usize,
+    pub methods: usize,
+    /// `only_left` holds the API calls of `version` missing in the package,
+    /// `only_right` the calls of the package missing in `version`.
+    pub diff: ApiDiff,
+}
+
+/// The database was trained with another framework API list, so none of its
+/// vectors can match.
+#[derive(Debug)]
+pub struct ApiListMismatch {
+    pub database: u64,
+    pub detector: u64,
+}
+
+impl fmt::Display for ApiListMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "The database was trained with another api list ({:016x}, not {:016x})",
+            self.database, self.detector
+        ))
+    }
+}
+
+impl std::error::Error for ApiListMismatch {}
+
+/// Looks up package features in a database, indexed by the stable hash of
+/// the API vectors of every library version and by the APIs they invoke.
+pub struct Detector<'a> {
+    apis: FrameworkApis,
+    matcher: Matcher,
+    api_list: u64,
+    index: HashMap<u64, Vec<&'a Library>>,
+    /// Every library version, by id in `api_index`.
+    versions: Vec<(&'a Library, &'a LibraryVersion)>,
+    api_index: VectorIndex,
+}
+
+impl<'a> Detector<'a> {
+    pub fn new(db: &'a Database, apis: FrameworkApis) -> Self {
+        let mut index: HashMap<u64, Vec<&'a Library>> = HashMap::new();
+        let mut versions = Vec::new();
+        let mut api_index = VectorIndex::default();
+        for lib in &db.libraries {
+            for version in &lib.versions {
+                index
+                    .entry(version.api_calls.stable_hash())
+                    .or_default()
+                    .push(lib);
+                api_index.insert(&version.api_calls);
+                versions.push((lib, version));
+            }
+        }
+        Detector {
+            apis,
+            matcher: Matcher::default(),
+            api_list: db.api_list,
+            index,
+            versions,
+            api_index,
+        }
+    }
+
+    /// Also reports packages whose API vector is similar to a library
+    /// version according to `matcher`, not only identical ones.
+    pub fn with_matcher(mut self, matcher: Matcher) -> Self {
+        self.matcher = matcher;
+        self
+    }
+
+    fn lookup<'b>(&'b self, feature: &'b PackageFeature) -> impl Iterator<Item = &'a Library> + 'b {
+        self.index
+            .get(&feature.api_calls.stable_hash())
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(move |lib| {
+                lib.versions
+                    .iter()
+                    .any(|v| v.api_calls == feature.api_calls)
+            })
+    }
+
+    /// The libraries with a version similar enough to `feature`, along with
+    /// the best similarity among their versions.
+    fn fuzzy_lookup(&self, feature: &PackageFeature) -> Vec<(&'a Library, f64)> {
+        // Ids follow the order of the libraries, so versions of the same
+        // library are adjacent
+        let mut found: Vec<(&'a Library, f64)> = Vec::new();
+        for id in self.api_index.candidates(&feature.api_calls) {
+            let (lib, version) = self.versions[id];
+            let similarity = match self.matcher.matches(&version.api_calls, &feature.api_calls) {
+                Some(similarity) => similarity,
+                None => continue,
+            };
+            match found.last_mut() {
+                Some((last, best)) if std::ptr::eq(*last, lib) => {
+                    if similarity >= *best {
+                        *best = similarity;
+                    }
+                }
+                _ => found.push((lib, similarity)),
+            }
+        }
+        found
+    }
+
+    /// Matches a package against the database and picks the closest version
+    /// among the libraries it matched. Identical API vectors are looked up
+    /// first; similar ones are only searched when the matcher allows them.
+    pub fn match_feature(&self, feature: &PackageFeature) -> Option<Detection> {
+        let mut candidates: Vec<(&'a Library, f64)> =
+            self.lookup(feature).map(|lib| (lib, 1.0)).collect();
+        if candidates.is_empty() && !self.matcher.is_exact() {
+            candidates = self.fuzzy_lookup(feature);
+        }
+
+        candidates
+            .into_iter()
+            .filter_map(|(lib, similarity)| {
+                lib.closest_version(feature)
+                    .map(|(version, confidence)| (lib, version, similarity, confidence))
+            })
+            .max_by(|a, b| {
+                (a.2, a.3)
+                    .partial_cmp(&(b.2, b.3))
+                    .unwrap_or(Ordering::Equal)
+            })
+            .map(|(lib, version, similarity, confidence)| Detection {
+                library: lib.name.clone(),
+                version: version.version.clone(),
+                confidence,
+                package: feature.package.clone(),
+                similarity,
+                classes: feature.classes,
+                methods: feature.methods,
+                diff: ApiDiff::new(&version.api_calls, &feature.api_calls),
+            })
+    }
+
+    fn check_api_list(&self) -> Result<(), ApiListMismatch> {
+        if self.api_list != self.apis.stable_hash() {
+            return Err(ApiListMismatch {
+                database: self.api_list,
+                detector: self.apis.stable_hash(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Fails when the database was not trained with the same API list.
+    pub fn detect(&self, apk: &Apk) -> Result<Vec<Detection>, Box<dyn std::error::Error>> {
+        self.check_api_list()?;
+        Ok(self.detect_features(&package_features(apk, &self.apis)?))
+    }
+
+    /// Like `detect`, but skips the dex files that cannot be loaded instead
+    /// of failing, and returns their errors along with the detections.
+    pub fn detect_partial(
+        &self,
+        apk: &Apk,
+    ) -> Result<(Vec<Detection>, Vec<DexLoadError>), Box<dyn std::error::Error>> {
+        self.check_api_list()?;
+        let (features, errors) = package_features_partial(apk, &self.apis)?;
+        Ok((self.detect_features(&features), errors))
+    }
+
+    /// Matches every package feature against the database. When a package and
+    /// one of its subpackages match the same library only the subpackage is
+    /// reported, since the outer package just wraps it.
+    pub fn detect_features(&self, features: &[PackageFeature]) -> Vec<Detection> {
+        let matches: Vec<Detection> = features
+            .iter()
+            .filter_map(|feature| self.match_feature(feature))
+            .collect();
+
+        matches
+            .iter()
+            .filter(|d| {
+                !matches.iter().any(|other| {
+                    other.library == d.library
+                        && other.package.len() > d.package.len()
+                        && other.package.starts_with(&d.package)
+                })
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::LibraryVersion;
+    use crate::features::ApiVector;
+    use crate::similarity::Metric;
+    use std::collections::BTreeSet;
+
+    fn api_calls(apis: &[(&str, usize)]) -> ApiVector {
+        let mut v = ApiVector::new();
+        for (api, count) in apis {
+            v.add(api, *count);
+        }
+        v
+    }
+
+    fn feature(package: &str, classes: &[&str], api_calls: ApiVector) -> PackageFeature {
+        PackageFeature {
+            package: String::from(package),
+            classes: classes.len(),
+            methods: 10,
+            class_names: classes.iter().map(|c| String::from(*c)).collect(),
+            api_calls,
+        }
+    }
+
+    #[test]
+    fn test_detect_features() {
+        let apis = api_calls(&[("Ljava/net/Socket;->connect", 1)]);
+        let class_names: BTreeSet<String> = ["Call;", "Request;"]
+            .iter()
+            .map(|c| String::from(*c))
+            .collect();
+        let db = Database {
+            api_list: FrameworkApis::default().stable_hash(),
+            libraries: vec![Library {
+                name: String::from("Lokhttp3/"),
+                versions: vec![LibraryVersion {
+                    version: String::from("3.12.0"),
+                    apps: 12,
+                    classes: 2,
+                    methods: 10,
+                    class_names,
+                    api_calls: apis.clone(),
+                }],
+            }],
+        };
+        let features = [
+            feature("La/", &["b/a;", "b/b;"], apis.clone()),
+            feature("La/b/", &["a;", "b;"], apis.clone()),
+            feature(
+                "Lcom/app/",
+                &["Main;"],
+                api_calls(&[("Ljava/io/File;->delete", 1)]),
+            ),
+        ];
+        let detector = Detector::new(&db, FrameworkApis::default());
+        let detections = detector.detect_features(&features);
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].library, "Lokhttp3/");
+        assert_eq!(detections[0].version, "3.12.0");
+        assert_eq!(detections[0].package, "La/b/");
+        assert!(detections[0].confidence < 1.0);
+
+        let clear = feature("Lokhttp3/", &["Call;", "Request;"], apis);
+        let detection = detector.match_feature(&clear).expect("not detected");
+        assert!((detection.confidence - 1.0).abs() < 1e-9);
+        assert!(detection.diff.is_empty());
+    }
+
+    #[test]
+    fn test_fuzzy_match() {
+        let apis = api_calls(&[
+            ("Ljava/net/Socket;->connect", 9),
+            ("Ljava/net/Socket;->close", 1),
+        ]);
+        let db = Database {
+            api_list: FrameworkApis::default().stable_hash(),
+            libraries: vec![Library {
+                name: String::from("Lokhttp3/"),
+                versions: vec![LibraryVersion {
+                    version: String::from("3.12.0"),
+                    apps: 12,
+                    classes: 1,
+                    methods: 10,
+                    class_names: BTreeSet::new(),
+                    api_calls: apis,
+                }],
+            }],
+        };
+        let shrunk = feature(
+            "La/",
+            &["a;"],
+            api_calls(&[("Ljava/net/Socket;->connect", 9)]),
+        );
+
+        let exact = Detector::new(&db, FrameworkApis::default());
+        assert!(exact.match_feature(&shrunk).is_none());
+
+        let fuzzy = Detector::new(&db, FrameworkApis::default())
+            .with_matcher(Matcher::new(Metric::WeightedJaccard, 0.85));
+        let detection = fuzzy.match_feature(&shrunk).expect("not detected");
+        assert!((detection.similarity - 0.9).abs() < 1e-9);
+        assert_eq!(
+            detection.diff.only_left,
+            [(String::from("Ljava/net/Socket;->close"), 1)]
+        );
+        assert!(detection.diff.only_right.is_empty());
+    }
+}
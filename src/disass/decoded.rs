@@ -0,0 +1,765 @@
+use crate::disass::opcodes::*;
+use crate::disass::Inst;
+
+/// A virtual register number.
+pub type Reg = u16;
+
+/// Kind of value moved, returned or stored by an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueKind {
+    Single,
+    Wide,
+    Object,
+}
+
+/// Kind of array element or field accessed by `aget`, `iput`, `sget`...
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    Int,
+    Wide,
+    Object,
+    Boolean,
+    Byte,
+    Char,
+    Short,
+}
+
+const ACCESS_KINDS: [AccessKind; 7] = [
+    AccessKind::Int,
+    AccessKind::Wide,
+    AccessKind::Object,
+    AccessKind::Boolean,
+    AccessKind::Byte,
+    AccessKind::Char,
+    AccessKind::Short,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InvokeKind {
+    Virtual,
+    Super,
+    Direct,
+    Static,
+    Interface,
+    Polymorphic,
+}
+
+const INVOKE_KINDS: [InvokeKind; 5] = [
+    InvokeKind::Virtual,
+    InvokeKind::Super,
+    InvokeKind::Direct,
+    InvokeKind::Static,
+    InvokeKind::Interface,
+];
+
+/// Condition of the `if-*` and `if-*z` instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IfCond {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Gt,
+    Le,
+}
+
+const IF_CONDS: [IfCond; 6] = [
+    IfCond::Eq,
+    IfCond::Ne,
+    IfCond::Lt,
+    IfCond::Ge,
+    IfCond::Gt,
+    IfCond::Le,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CmpKind {
+    LFloat,
+    GFloat,
+    LDouble,
+    GDouble,
+    Long,
+}
+
+const CMP_KINDS: [CmpKind; 5] = [
+    CmpKind::LFloat,
+    CmpKind::GFloat,
+    CmpKind::LDouble,
+    CmpKind::GDouble,
+    CmpKind::Long,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SwitchKind {
+    Packed,
+    Sparse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NumType {
+    Int,
+    Long,
+    Float,
+    Double,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    Neg(NumType),
+    Not(NumType),
+    Convert(NumType, NumType),
+    IntToByte,
+    IntToChar,
+    IntToShort,
+}
+
+const UNARY_OPS: [UnaryOp; 21] = [
+    UnaryOp::Neg(NumType::Int),
+    UnaryOp::Not(NumType::Int),
+    UnaryOp::Neg(NumType::Long),
+    UnaryOp::Not(NumType::Long),
+    UnaryOp::Neg(NumType::Float),
+    UnaryOp::Neg(NumType::Double),
+    UnaryOp::Convert(NumType::Int, NumType::Long),
+    UnaryOp::Convert(NumType::Int, NumType::Float),
+    UnaryOp::Convert(NumType::Int, NumType::Double),
+    UnaryOp::Convert(NumType::Long, NumType::Int),
+    UnaryOp::Convert(NumType::Long, NumType::Float),
+    UnaryOp::Convert(NumType::Long, NumType::Double),
+    UnaryOp::Convert(NumType::Float, NumType::Int),
+    UnaryOp::Convert(NumType::Float, NumType::Long),
+    UnaryOp::Convert(NumType::Float, NumType::Double),
+    UnaryOp::Convert(NumType::Double, NumType::Int),
+    UnaryOp::Convert(NumType::Double, NumType::Long),
+    UnaryOp::Convert(NumType::Double, NumType::Float),
+    UnaryOp::IntToByte,
+    UnaryOp::IntToChar,
+    UnaryOp::IntToShort,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+    Ushr,
+    /// Reverse subtraction, `literal - register`. Only used with literals.
+    Rsub,
+}
+
+const BINARY_OPS: [BinaryOp; 11] = [
+    BinaryOp::Add,
+    BinaryOp::Sub,
+    BinaryOp::Mul,
+    BinaryOp::Div,
+    BinaryOp::Rem,
+    BinaryOp::And,
+    BinaryOp::Or,
+    BinaryOp::Xor,
+    BinaryOp::Shl,
+    BinaryOp::Shr,
+    BinaryOp::Ushr,
+];
+
+const LITERAL_OPS: [BinaryOp; 11] = [
+    BinaryOp::Add,
+    BinaryOp::Rsub,
+    BinaryOp::Mul,
+    BinaryOp::Div,
+    BinaryOp::Rem,
+    BinaryOp::And,
+    BinaryOp::Or,
+    BinaryOp::Xor,
+    BinaryOp::Shl,
+    BinaryOp::Shr,
+    BinaryOp::Ushr,
+];
+
+/// Operation and operand type of the n-th arithmetic opcode, counting from
+/// `add-int` or `add-int/2addr`.
+fn binary_op(n: usize) -> (BinaryOp, NumType) {
+    match n {
+        0..=10 => (BINARY_OPS[n], NumType::Int),
+        11..=21 => (BINARY_OPS[n - 11], NumType::Long),
+        22..=26 => (BINARY_OPS[n - 22], NumType::Float),
+        _ => (BINARY_OPS[n - 27], NumType::Double),
+    }
+}
+
+/// An instruction with its operands decoded according to its format.
+///
+/// Offsets are relative to the address of the instruction, in 16-bit code
+/// units, and literals are sign extended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodedInst {
+    Nop,
+    /// Switch or array data payload stored in the middle of the code.
+    Payload,
+    Move {
+        kind: ValueKind,
+        dst: Reg,
+        src: Reg,
+    },
+    MoveResult {
+        kind: ValueKind,
+        dst: Reg,
+    },
+    MoveException {
+        dst: Reg,
+    },
+    ReturnVoid,
+    Return {
+        kind: ValueKind,
+        src: Reg,
+    },
+    Const {
+        dst: Reg,
+        value: i64,
+        wide: bool,
+    },
+    ConstString {
+        dst: Reg,
+        string_idx: u32,
+    },
+    ConstClass {
+        dst: Reg,
+        type_idx: u32,
+    },
+    ConstMethodHandle {
+        dst: Reg,
+        handle_idx: u32,
+    },
+    ConstMethodType {
+        dst: Reg,
+        proto_idx: u32,
+    },
+    MonitorEnter {
+        reg: Reg,
+    },
+    MonitorExit {
+        reg: Reg,
+    },
+    CheckCast {
+        reg: Reg,
+        type_idx: u32,
+    },
+    InstanceOf {
+        dst: Reg,
+        src: Reg,
+        type_idx: u32,
+    },
+    ArrayLength {
+        dst: Reg,
+        array: Reg,
+    },
+    NewInstance {
+        dst: Reg,
+        type_idx: u32,
+    },
+    NewArray {
+        dst: Reg,
+        size: Reg,
+        type_idx: u32,
+    },
+    FilledNewArray {
+        type_idx: u32,
+        args: Vec<Reg>,
+    },
+    FillArrayData {
+        array: Reg,
+        offset: i32,
+    },
+    Throw {
+        reg: Reg,
+    },
+    Goto {
+        offset: i32,
+    },
+    Switch {
+        kind: SwitchKind,
+        reg: Reg,
+        offset: i32,
+    },
+    Cmp {
+        kind: CmpKind,
+        dst: Reg,
+        a: Reg,
+        b: Reg,
+    },
+    IfTest {
+        cond: IfCond,
+        a: Reg,
+        b: Reg,
+        offset: i32,
+    },
+    IfTestZ {
+        cond: IfCond,
+        a: Reg,
+        offset: i32,
+    },
+    Aget {
+        kind: AccessKind,
+        dst: Reg,
+        array: Reg,
+        index: Reg,
+    },
+    Aput {
+        kind: AccessKind,
+        src: Reg,
+        array: Reg,
+        index: Reg,
+    },
+    Iget {
+        kind: AccessKind,
+        dst: Reg,
+        object: Reg,
+        field_idx: u32,
+    },
+    Iput {
+        kind: AccessKind,
+        src: Reg,
+        object: Reg,
+        field_idx: u32,
+    },
+    Sget {
+        kind: AccessKind,
+        dst: Reg,
+        field_idx: u32,
+    },
+    Sput {
+        kind: AccessKind,
+        src: Reg,
+        field_idx: u32,
+    },
+    /// `proto_idx` is only set for `invoke-polymorphic`.
+    Invoke {
+        kind: InvokeKind,
+        method_idx: u32,
+        args: Vec<Reg>,
+        proto_idx: Option<u32>,
+    },
+    InvokeCustom {
+        call_site_idx: u32,
+        args: Vec<Reg>,
+    },
+    Unary {
+        op: UnaryOp,
+        dst: Reg,
+        src: Reg,
+    },
+    /// `dst = a op b`. The `/2addr` forms have `dst == a`.
+    Binary {
+        op: BinaryOp,
+        ty: NumType,
+        dst: Reg,
+        a: Reg,
+        b: Reg,
+    },
+    BinaryLiteral {
+        op: BinaryOp,
+        dst: Reg,
+        src: Reg,
+        literal: i32,
+    },
+    Unused {
+        op: u8,
+    },
+}
+
+impl Inst<'_> {
+    /// Registers of the `{vC, vD, vE, vF, vG}` list of the 35c formats.
+    pub(super) fn arg_list(&self) -> Vec<Reg> {
+        let regs = [
+            self.get_c(),
+            self.get_d(),
+            self.get_e(),
+            self.get_f(),
+            self.get_g(),
+        ];
+        let count = (self.get_a() as usize).min(regs.len());
+        regs[..count].iter().map(|r| *r as Reg).collect()
+    }
+
+    /// Registers of the `{vCCCC .. vNNNN}` range of the 3rc formats.
+    pub(super) fn arg_range(&self) -> Vec<Reg> {
+        let first = self.get_c();
+        (first..first + self.get_a()).map(|r| r as Reg).collect()
+    }
+
+    /// Decodes the operands of the instruction using the getter of its
+    /// format in the instruction table.
+    pub fn decode(&self) -> DecodedInst {
+        let op = self.op();
+        let a = || self.get_a() as Reg;
+        let b = || self.get_b() as Reg;
+        let c = || self.get_c() as Reg;
+        let idx_b = || self.get_b() as u32;
+        let idx_c = || self.get_c() as u32;
+        let offset = |v: u64| v as i64 as i32;
+        match op {
+            NOP if self.bytes[1] == 0 => DecodedInst::Nop,
+            NOP => DecodedInst::Payload,
+            MOVE..=MOVE16 => DecodedInst::Move {
+                kind: ValueKind::Single,
+                dst: a(),
+                src: b(),
+            },
+            MOVEWIDE..=MOVEWIDE16 => DecodedInst::Move {
+                kind: ValueKind::Wide,
+                dst: a(),
+                src: b(),
+            },
+            MOVEOBJECT..=MOVEOBJECT16 => DecodedInst::Move {
+                kind: ValueKind::Object,
+                dst: a(),
+                src: b(),
+            },
+            MOVERESULT => DecodedInst::MoveResult {
+                kind: ValueKind::Single,
+                dst: a(),
+            },
+            MOVERESULTWIDE => DecodedInst::MoveResult {
+                kind: ValueKind::Wide,
+                dst: a(),
+            },
+            MOVERESULTOBJECT => DecodedInst::MoveResult {
+                kind: ValueKind::Object,
+                dst: a(),
+            },
+            MOVEEXCEPTION => DecodedInst::MoveException { dst: a() },
+            RETURNVOID => DecodedInst::ReturnVoid,
+            RETURN => DecodedInst::Return {
+                kind: ValueKind::Single,
+                src: a(),
+            },
+            RETURNWIDE => DecodedInst::Return {
+                kind: ValueKind::Wide,
+                src: a(),
+            },
+            RETURNOBJECT => DecodedInst::Return {
+                kind: ValueKind::Object,
+                src: a(),
+            },
+            CONST4..=CONSTWIDEHIGH16 => {
+                let raw = self.get_b();
+                let value = match op {
+                    CONST4 => ((raw as u8) << 4) as i8 as i64 >> 4,
+                    CONST16 | CONSTWIDE16 => raw as u16 as i16 as i64,
+                    CONST | CONSTWIDE32 => raw as u32 as i32 as i64,
+                    CONSTHIGH16 => ((raw as u32) << 16) as i32 as i64,
+                    CONSTWIDEHIGH16 => (raw << 48) as i64,
+                    _ => raw as i64,
+                };
+                DecodedInst::Const {
+                    dst: a(),
+                    value,
+                    wide: op >= CONSTWIDE16,
+                }
+            }
+            CONSTSTRING | CONSTSTRINGJUMBO => DecodedInst::ConstString {
+                dst: a(),
+                string_idx: idx_b(),
+            },
+            CONSTCLASS => DecodedInst::ConstClass {
+                dst: a(),
+                type_idx: idx_b(),
+            },
+            MONITORENTER => DecodedInst::MonitorEnter { reg: a() },
+            MONITOREXIT => DecodedInst::MonitorExit { reg: a() },
+            CHECKCAST => DecodedInst::CheckCast {
+                reg: a(),
+                type_idx: idx_b(),
+            },
+            INSTANCEOF => DecodedInst::InstanceOf {
+                dst: a(),
+                src: b(),
+                type_idx: idx_c(),
+            },
+            ARRAYLENGTH => DecodedInst::ArrayLength {
+                dst: a(),
+                array: b(),
+            },
+            NEWINSTANCE => DecodedInst::NewInstance {
+                dst: a(),
+                type_idx: idx_b(),
+            },
+            NEWARRAY => DecodedInst::NewArray {
+                dst: a(),
+                size: b(),
+                type_idx: idx_c(),
+            },
+            FILLEDNEWARRAY => DecodedInst::FilledNewArray {
+                type_idx: idx_b(),
+                args: self.arg_list(),
+            },
+            FILLEDNEWARRAY_RANGE => DecodedInst::FilledNewArray {
+                type_idx: idx_b(),
+                args: self.arg_range(),
+            },
+            FILLARRAYDATA => DecodedInst::FillArrayData {
+                array: a(),
+                offset: offset(self.get_b()),
+            },
+            THROW => DecodedInst::Throw { reg: a() },
+            GOTO..=GOTO_32 => DecodedInst::Goto {
+                offset: offset(self.get_a()),
+            },
+            PACKEDSWITCH | SPARSESWITCH => DecodedInst::Switch {
+                kind: if op == PACKEDSWITCH {
+                    SwitchKind::Packed
+                } else {
+                    SwitchKind::Sparse
+                },
+                reg: a(),
+                offset: offset(self.get_b()),
+            },
+            CMPLFLOAT..=CMPLONG => DecodedInst::Cmp {
+                kind: CMP_KINDS[op - CMPLFLOAT],
+                dst: a(),
+                a: b(),
+                b: c(),
+            },
+            IFEQ..=IFLE => DecodedInst::IfTest {
+                cond: IF_CONDS[op - IFEQ],
+                a: a(),
+                b: b(),
+                offset: offset(self.get_c()),
+            },
+            IFEQZ..=IFLEZ => DecodedInst::IfTestZ {
+                cond: IF_CONDS[op - IFEQZ],
+                a: a(),
+                offset: offset(self.get_b()),
+            },
+            AGET..=AGETSHORT => DecodedInst::Aget {
+                kind: ACCESS_KINDS[op - AGET],
+                dst: a(),
+                array: b(),
+                index: c(),
+            },
+            APUT..=APUTSHORT => DecodedInst::Aput {
+                kind: ACCESS_KINDS[op - APUT],
+                src: a(),
+                array: b(),
+                index: c(),
+            },
+            IGET..=IGETSHORT => DecodedInst::Iget {
+                kind: ACCESS_KINDS[op - IGET],
+                dst: a(),
+                object: b(),
+                field_idx: idx_c(),
+            },
+            IPUT..=IPUTSHORT => DecodedInst::Iput {
+                kind: ACCESS_KINDS[op - IPUT],
+                src: a(),
+                object: b(),
+                field_idx: idx_c(),
+            },
+            SGET..=SGETSHORT => DecodedInst::Sget {
+                kind: ACCESS_KINDS[op - SGET],
+                dst: a(),
+                field_idx: idx_b(),
+            },
+            SPUT..=SPUTSHORT => DecodedInst::Sput {
+                kind: ACCESS_KINDS[op - SPUT],
+                src: a(),
+                field_idx: idx_b(),
+            },
+            INVOKEVIRTUAL..=INVOKEINTERFACE => DecodedInst::Invoke {
+                kind: INVOKE_KINDS[op - INVOKEVIRTUAL],
+                method_idx: idx_b(),
+                args: self.arg_list(),
+                proto_idx: None,
+            },
+            INVOKEVIRTUAL_RANGE..=INVOKEINTERFACE_RANGE => DecodedInst::Invoke {
+                kind: INVOKE_KINDS[op - INVOKEVIRTUAL_RANGE],
+                method_idx: idx_b(),
+                args: self.arg_range(),
+                proto_idx: None,
+            },
+            INVOKEPOLYMORPHIC | INVOKEPOLYMORPHIC_RANGE => DecodedInst::Invoke {
+                kind: InvokeKind::Polymorphic,
+                method_idx: idx_b(),
+                args: if op == INVOKEPOLYMORPHIC {
+                    self.arg_list()
+                } else {
+                    self.arg_range()
+                },
+                proto_idx: Some(self.get_h() as u32),
+            },
+            INVOKE_CUSTOM => DecodedInst::InvokeCustom {
+                call_site_idx: idx_b(),
+                args: self.arg_list(),
+            },
+            INVOKE_CUSTOM_RANGE => DecodedInst::InvokeCustom {
+                call_site_idx: idx_b(),
+                args: self.arg_range(),
+            },
+            NEGINT..=INTTOSHORT => DecodedInst::Unary {
+                op: UNARY_OPS[op - NEGINT],
+                dst: a(),
+                src: b(),
+            },
+            ADDINT..=REMDOUBLE => {
+                let (bop, ty) = binary_op(op - ADDINT);
+                DecodedInst::Binary {
+                    op: bop,
+                    ty,
+                    dst: a(),
+                    a: b(),
+                    b: c(),
+                }
+            }
+            ADDINT_2ADDR..=REMDOUBLE_2ADDR => {
+                let (bop, ty) = binary_op(op - ADDINT_2ADDR);
+                DecodedInst::Binary {
+                    op: bop,
+                    ty,
+                    dst: a(),
+                    a: a(),
+                    b: b(),
+                }
+            }
+            ADDINT_LIT16..=XORINT_LIT16 => DecodedInst::BinaryLiteral {
+                op: LITERAL_OPS[op - ADDINT_LIT16],
+                dst: a(),
+                src: b(),
+                literal: self.get_c() as u16 as i16 as i32,
+            },
+            ADDINT_LIT8..=USHRINT_LIT8 => DecodedInst::BinaryLiteral {
+                op: LITERAL_OPS[op - ADDINT_LIT8],
+                dst: a(),
+                src: b(),
+                literal: self.get_c() as u8 as i8 as i32,
+            },
+            CONSTMETHODHANDLE => DecodedInst::ConstMethodHandle {
+                dst: a(),
+                handle_idx: idx_b(),
+            },
+            CONSTMETHODTYPE => DecodedInst::ConstMethodType {
+                dst: a(),
+                proto_idx: idx_b(),
+            },
+            _ => DecodedInst::Unused { op: op as u8 },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_decode {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> DecodedInst {
+        Inst { bytes, addr: 0 }.decode()
+    }
+
+    #[test]
+    fn test_const() {
+        // const/4 v1, #-1
+        assert_eq!(
+            decode(&[0x12, 0xf1]),
+            DecodedInst::Const {
+                dst: 1,
+                value: -1,
+                wide: false
+            }
+        );
+        // const/high16 v0, #0x7f010000
+        assert_eq!(
+            decode(&[0x15, 0x00, 0x01, 0x7f]),
+            DecodedInst::Const {
+                dst: 0,
+                value: 0x7f01_0000,
+                wide: false
+            }
+        );
+        // const-string v2, string@0x1234
+        assert_eq!(
+            decode(&[0x1a, 0x02, 0x34, 0x12]),
+            DecodedInst::ConstString {
+                dst: 2,
+                string_idx: 0x1234
+            }
+        );
+    }
+
+    #[test]
+    fn test_invoke() {
+        // invoke-virtual {v1, v2, v3}, meth@0x0010
+        assert_eq!(
+            decode(&[0x6e, 0x30, 0x10, 0x00, 0x21, 0x03]),
+            DecodedInst::Invoke {
+                kind: InvokeKind::Virtual,
+                method_idx: 0x10,
+                args: vec![1, 2, 3],
+                proto_idx: None
+            }
+        );
+        // invoke-static/range {v4 .. v6}, meth@0x0020
+        assert_eq!(
+            decode(&[0x77, 0x03, 0x20, 0x00, 0x04, 0x00]),
+            DecodedInst::Invoke {
+                kind: InvokeKind::Static,
+                method_idx: 0x20,
+                args: vec![4, 5, 6],
+                proto_idx: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_branches() {
+        // if-ne v1, v2, -4
+        assert_eq!(
+            decode(&[0x33, 0x21, 0xfc, 0xff]),
+            DecodedInst::IfTest {
+                cond: IfCond::Ne,
+                a: 1,
+                b: 2,
+                offset: -4
+            }
+        );
+        // goto +3
+        assert_eq!(decode(&[0x28, 0x03]), DecodedInst::Goto { offset: 3 });
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        // add-int v0, v1, v2
+        assert_eq!(
+            decode(&[0x90, 0x00, 0x01, 0x02]),
+            DecodedInst::Binary {
+                op: BinaryOp::Add,
+                ty: NumType::Int,
+                dst: 0,
+                a: 1,
+                b: 2
+            }
+        );
+        // rsub-int/lit8 v0, v1, #-2
+        assert_eq!(
+            decode(&[0xd9, 0x00, 0x01, 0xfe]),
+            DecodedInst::BinaryLiteral {
+                op: BinaryOp::Rsub,
+                dst: 0,
+                src: 1,
+                literal: -2
+            }
+        );
+        // double-to-float v0, v2
+        assert_eq!(
+            decode(&[0x8c, 0x20]),
+            DecodedInst::Unary {
+                op: UnaryOp::Convert(NumType::Double, NumType::Float),
+                dst: 0,
+                src: 2
+            }
+        );
+    }
+}
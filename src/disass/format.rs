@@ -0,0 +1,250 @@
+use crate::disass::opcodes::*;
+use crate::disass::{DecodedInst, Inst, INSTTYPES};
+
+/// Resolves the pool indices referenced by instructions into their smali
+/// representation. Unresolved indices are printed as `kind@index`.
+pub trait Resolver {
+    fn string(&self, _idx: u64) -> Option<String> {
+        None
+    }
+
+    fn type_descriptor(&self, _idx: u64) -> Option<String> {
+        None
+    }
+
+    fn field(&self, _idx: u64) -> Option<String> {
+        None
+    }
+
+    fn method(&self, _idx: u64) -> Option<String> {
+        None
+    }
+
+    fn proto(&self, _idx: u64) -> Option<String> {
+        None
+    }
+}
+
+/// Resolver that leaves every index unresolved.
+pub struct NoResolver;
+
+impl Resolver for NoResolver {}
+
+/// Smali label of the code unit `target`, as referenced by the instruction
+/// with opcode `op`.
+pub fn label_name(op: usize, target: usize) -> String {
+    let prefix = match op {
+        GOTO..=GOTO_32 => "goto",
+        IFEQ..=IFLEZ => "cond",
+        PACKEDSWITCH => "pswitch_data",
+        SPARSESWITCH => "sswitch_data",
+        FILLARRAYDATA => "array",
+        _ => "label",
+    };
+    format!(":{}_{:x}", prefix, target)
+}
+
+/// Formats an integer literal like baksmali does, e.g. `-0x1` or `0x10L`.
+pub fn format_literal(value: i64, wide: bool) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let suffix = if wide { "L" } else { "" };
+    format!("{}0x{:x}{}", sign, value.unsigned_abs(), suffix)
+}
+
+/// Escapes a string constant and wraps it in double quotes.
+pub fn quote_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\'' => out.push_str("\\'"),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (' '..='~').contains(&c) => out.push(c),
+            c => {
+                let mut buf = [0u16; 2];
+                for unit in c.encode_utf16(&mut buf) {
+                    out.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl Inst<'_> {
+    pub(super) fn operand(&self, name: char) -> u64 {
+        match name {
+            'A' => self.get_a(),
+            'B' => self.get_b(),
+            'C' => self.get_c(),
+            'D' => self.get_d(),
+            'E' => self.get_e(),
+            'F' => self.get_f(),
+            'G' => self.get_g(),
+            _ => self.get_h(),
+        }
+    }
+
+    fn literal(&self) -> String {
+        match self.decode() {
+            DecodedInst::Const { value, wide, .. } => format_literal(value, wide),
+            DecodedInst::BinaryLiteral { literal, .. } => format_literal(literal.into(), false),
+            _ => String::new(),
+        }
+    }
+
+    fn reference(&self, kind: &str, idx: u64, resolver: &dyn Resolver) -> String {
+        let resolved = match kind {
+            "string" => resolver.string(idx),
+            "type" => resolver.type_descriptor(idx),
+            "field" => resolver.field(idx),
+            "meth" => resolver.method(idx),
+            "proto" => resolver.proto(idx),
+            _ => None,
+        };
+        resolved.unwrap_or_else(|| format!("{}@{}", kind, idx))
+    }
+
+    /// Formats the instruction in smali syntax. Branch targets are rendered
+    /// as labels, or as relative offsets when they fall before the method.
+    pub fn format(&self, resolver: &dyn Resolver) -> String {
+        if self.op() == NOP && self.bytes[1] != 0 {
+            let payload = match self.bytes[1] {
+                1 => "packed-switch",
+                2 => "sparse-switch",
+                _ => "fill-array-data",
+            };
+            return format!("# {} payload", payload);
+        }
+
+        let syntax = INSTTYPES[self.op()].syntax;
+        let mut out = String::from(self.mnemonic());
+        if !syntax.is_empty() {
+            out.push(' ');
+        }
+
+        let mut rest = syntax;
+        while let Some(c) = rest.chars().next() {
+            if let Some(tail) = rest.strip_prefix("{vC, vD, vE, vF, vG}") {
+                let regs = [
+                    self.get_c(),
+                    self.get_d(),
+                    self.get_e(),
+                    self.get_f(),
+                    self.get_g(),
+                ];
+                let count = (self.get_a() as usize).min(regs.len());
+                let regs: Vec<String> = regs[..count].iter().map(|r| format!("v{}", r)).collect();
+                out.push_str(&format!("{{{}}}", regs.join(", ")));
+                rest = tail;
+            } else if let Some(tail) = rest.strip_prefix("{vC .. vN}") {
+                let (first, count) = (self.get_c(), self.get_a());
+                if count == 0 {
+                    out.push_str("{}");
+                } else {
+                    out.push_str(&format!("{{v{} .. v{}}}", first, first + count - 1));
+                }
+                rest = tail;
+            } else if let Some(tail) = rest.strip_prefix('v') {
+                out.push_str(&format!("v{}", self.operand(tail.chars().next().unwrap())));
+                rest = &tail[1..];
+            } else if let Some(tail) = rest.strip_prefix("#+") {
+                out.push_str(&self.literal());
+                rest = tail[1..].trim_start_matches('0');
+            } else if let Some(tail) = rest.strip_prefix('+') {
+                let offset = self.operand(tail.chars().next().unwrap());
+                match self.relative(offset) {
+                    Some(target) => out.push_str(&label_name(self.op(), target)),
+                    None => out.push_str(&format!("{:+}", offset as i64)),
+                }
+                rest = &tail[1..];
+            } else if c.is_ascii_lowercase() {
+                let at = rest.find('@').unwrap_or(rest.len());
+                let kind = &rest[..at];
+                let name = rest[at + 1..].chars().next().unwrap();
+                out.push_str(&self.reference(kind, self.operand(name), resolver));
+                rest = &rest[at + 2..];
+            } else {
+                out.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test_format {
+    use super::*;
+
+    struct Names;
+
+    impl Resolver for Names {
+        fn string(&self, _idx: u64) -> Option<String> {
+            Some(quote_string("a\"b\n"))
+        }
+
+        fn method(&self, _idx: u64) -> Option<String> {
+            Some(String::from("Ljava/lang/Object;-><init>()V"))
+        }
+    }
+
+    fn format(bytes: &[u8], addr: usize) -> String {
+        Inst { bytes, addr }.format(&Names)
+    }
+
+    #[test]
+    fn test_registers_and_literals() {
+        assert_eq!(format(&[0x01, 0x21], 0), "move v1, v2");
+        assert_eq!(format(&[0x12, 0xf1], 0), "const/4 v1, -0x1");
+        assert_eq!(
+            format(&[0x15, 0x00, 0x01, 0x7f], 0),
+            "const/high16 v0, 0x7f010000"
+        );
+        assert_eq!(
+            format(&[0x16, 0x02, 0x10, 0x00], 0),
+            "const-wide/16 v2, 0x10L"
+        );
+        assert_eq!(
+            format(&[0xd8, 0x00, 0x01, 0xff], 0),
+            "add-int/lit8 v0, v1, -0x1"
+        );
+        assert_eq!(format(&[0x0e, 0x00], 0), "return-void");
+    }
+
+    #[test]
+    fn test_references() {
+        assert_eq!(
+            format(&[0x1a, 0x00, 0x01, 0x00], 0),
+            "const-string v0, \"a\\\"b\\n\""
+        );
+        assert_eq!(
+            format(&[0x1c, 0x00, 0x05, 0x00], 0),
+            "const-class v0, type@5"
+        );
+        assert_eq!(
+            format(&[0x70, 0x10, 0x01, 0x00, 0x03, 0x00], 0),
+            "invoke-direct {v3}, Ljava/lang/Object;-><init>()V"
+        );
+        assert_eq!(
+            format(&[0x74, 0x02, 0x01, 0x00, 0x04, 0x00], 0),
+            "invoke-virtual/range {v4 .. v5}, Ljava/lang/Object;-><init>()V"
+        );
+    }
+
+    #[test]
+    fn test_branches() {
+        assert_eq!(format(&[0x28, 0xfe], 6), "goto :goto_4");
+        assert_eq!(format(&[0x28, 0xfe], 0), "goto -2");
+        assert_eq!(format(&[0x38, 0x01, 0x10, 0x00], 2), "if-eqz v1, :cond_12");
+        assert_eq!(
+            format(&[0x2b, 0x03, 0x08, 0x00, 0x00, 0x00], 0),
+            "packed-switch v3, :pswitch_data_8"
+        );
+    }
+}
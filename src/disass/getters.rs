@@ -347,11 +347,11 @@ impl InstGetter for GetterOpAACCBB {
     }
 
     fn b(&self, data: &[u8]) -> u64 {
-        data[3].into()
+        data[2].into()
     }
 
     fn c(&self, data: &[u8]) -> u64 {
-        data[2].into()
+        data[3].into()
     }
 
     fn d(&self, _data: &[u8]) -> u64 {
@@ -951,7 +951,7 @@ mod test_getters {
         panics_on!(Getter20t, b);
         //        panics_on!(GetterOpAABBBB, b);
         //        panics_on!(Getter21t, b);
-        //        panics_on!(GetterOpAACCBB, b);
+        gets_byte_at_pos!(GetterOpAACCBB, b, 2);
         //        panics_on!(GetterOpBACCCC, b);
         //        panics_on!(Getter22t, b);
         panics_on!(GetterOp00AAAAAAAA, b);
@@ -975,7 +975,7 @@ mod test_getters {
         panics_on!(Getter20t, c);
         panics_on!(GetterOpAABBBB, c);
         panics_on!(Getter21t, c);
-        //        panics_on!(GetterOpAACCBB, c);
+        gets_byte_at_pos!(GetterOpAACCBB, c, 3);
         //        panics_on!(GetterOpBACCCC, c);
         //        panics_on!(Getter22t, c);
         panics_on!(GetterOp00AAAAAAAA, c);
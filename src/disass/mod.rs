@@ -3,23 +3,37 @@ use dex::code::CodeItem;
 use std::convert::TryInto;
 use std::fmt;
 
+mod decoded;
+mod format;
 mod getters;
 #[allow(dead_code)]
 mod opcodes;
+mod payload;
+mod regs;
+mod tries;
 
+pub use crate::disass::decoded::*;
+pub use crate::disass::format::*;
 use crate::disass::getters::*;
 use crate::disass::opcodes::*;
+pub use crate::disass::payload::*;
+pub use crate::disass::tries::*;
 
 struct InstType {
     pub mnemonic: &'static str,
     pub syntax: &'static str,
     pub get: &'static dyn InstGetter,
+    /// Registers defined (`d`) and used (`u`) by the instruction, e.g.
+    /// `dA uB`. Uppercase letters mark wide register pairs, and `args` or
+    /// `range` the argument registers of invokes.
+    pub regs: &'static str,
 }
 
 const INSTTYPES: [InstType; 256] = include!("insn.in");
 
 pub struct Inst<'a> {
     bytes: &'a [u8],
+    addr: usize,
 }
 
 macro_rules! table {
@@ -33,40 +47,138 @@ impl Inst<'_> {
         self.bytes[0].into()
     }
 
-    pub fn length(&self) -> usize {
-        if self.op() == 0 {
-            if self.bytes[1] == 0 {
-                return 2;
-            } else if self.bytes[1] == 1 {
+    /// Checks that `bytes` starts with a complete and valid instruction and
+    /// returns it. `offset` is the code-unit address of the instruction.
+    pub fn parse(bytes: &[u8], offset: usize) -> Result<Inst<'_>, DisassError> {
+        let truncated = |needed: usize| DisassError::Truncated {
+            offset,
+            needed,
+            available: bytes.len(),
+        };
+        if bytes.len() < 2 {
+            return Err(truncated(2));
+        }
+
+        let op = bytes[0] as usize;
+        let length = if op == NOP && bytes[1] > 3 {
+            return Err(DisassError::UnknownPayload {
+                offset,
+                ident: bytes[1],
+            });
+        } else if op == NOP && bytes[1] != 0 {
+            let header = if bytes[1] == 3 { 8 } else { 4 };
+            if bytes.len() < header {
+                return Err(truncated(header));
+            }
+            let size = ((bytes[3] as usize) << 8) + (bytes[2] as usize);
+            match bytes[1] {
                 // packed-switch-payload
-                let size: usize = (((self.bytes[3] as usize) << 8) + (self.bytes[2] as usize))
-                    .try_into()
-                    .unwrap();
-                return 8 + 4 * size;
-            } else if self.bytes[1] == 2 {
+                1 => 8 + 4 * size,
                 // sparse-switch-payload
-                let size: usize = (((self.bytes[3] as usize) << 8) + (self.bytes[2] as usize))
-                    .try_into()
-                    .unwrap();
-                return 4 + 8 * size;
-            } else if self.bytes[1] == 3 {
+                2 => 4 + 8 * size,
                 // fill-array-data-payload
-                let w = ((self.bytes[3] as usize) << 8) + (self.bytes[2] as usize);
-                let n = ((self.bytes[7] as usize) << 24)
-                    + ((self.bytes[6] as usize) << 16)
-                    + ((self.bytes[5] as usize) << 8)
-                    + (self.bytes[4] as usize);
-                let mut len: usize = (8 + w * n).try_into().unwrap();
-                if len % 2 == 1 {
-                    len += 1;
+                _ => {
+                    let count = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+                    let len = (count as usize)
+                        .checked_mul(size)
+                        .and_then(|data| data.checked_add(8))
+                        .ok_or_else(|| truncated(usize::MAX))?;
+                    len + len % 2
                 }
-                return len;
             }
+        } else if INSTTYPES[op].mnemonic == "UNUSED" {
+            return Err(DisassError::InvalidOpcode {
+                offset,
+                op: op as u8,
+            });
+        } else {
+            INSTTYPES[op].get.length()
+        };
+
+        if bytes.len() < length {
+            return Err(truncated(length));
+        }
+        Ok(Inst {
+            bytes: &bytes[..length],
+            addr: offset,
+        })
+    }
+
+    /// Length of the instruction in bytes.
+    pub fn length(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Address of the instruction in the method, in 16-bit code units.
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    /// Address of the instruction that follows this one.
+    pub fn next_addr(&self) -> usize {
+        self.addr + self.length() / 2
+    }
+
+    fn relative(&self, offset: u64) -> Option<usize> {
+        // Offsets are signed and at most 32 bits wide, but the getters of the
+        // 30t and 31t formats zero-extend them
+        let target = self.addr as i64 + offset as i32 as i64;
+        if target < 0 {
+            None
+        } else {
+            Some(target as usize)
+        }
+    }
+
+    /// Absolute target of a `goto` or `if-*` instruction.
+    pub fn branch_target(&self) -> Option<usize> {
+        if self.is_goto() {
+            self.relative(self.get_a())
+        } else if IFEQ <= self.op() && self.op() <= IFLE {
+            self.relative(self.get_c())
+        } else if self.is_branch() {
+            self.relative(self.get_b())
+        } else {
+            None
+        }
+    }
 
-            panic!("Unexpected NOP type {:x}", self.bytes[1]);
+    /// Address of the payload of a switch or `fill-array-data` instruction.
+    pub fn payload_addr(&self) -> Option<usize> {
+        if self.is_switch() || self.op() == FILLARRAYDATA {
+            self.relative(self.get_b())
+        } else {
+            None
         }
+    }
 
-        return table!(self).length();
+    /// Case targets of a switch instruction, read from its payload in
+    /// `code`, the bytes of the whole method.
+    pub fn switch_targets(&self, code: &[u8]) -> Result<Vec<usize>, DisassError> {
+        let cases = self.switch_cases(code)?;
+        Ok(cases.into_iter().map(|(_, target)| target).collect())
+    }
+
+    /// Addresses where execution can continue after this instruction,
+    /// without taking exceptions into account. `code` holds the bytes of the
+    /// whole method and is only read for switches.
+    pub fn successors(&self, code: &[u8]) -> Result<Vec<usize>, DisassError> {
+        if self.is_return() || self.is_throw() || (self.op() == NOP && self.bytes[1] != 0) {
+            return Ok(Vec::new());
+        }
+        if self.is_goto() {
+            return Ok(self.branch_target().into_iter().collect());
+        }
+
+        let mut successors = vec![self.next_addr()];
+        if self.is_branch() {
+            successors.extend(self.branch_target());
+        } else if self.is_switch() {
+            successors.extend(self.switch_targets(code)?);
+        }
+        let mut seen = std::collections::HashSet::new();
+        successors.retain(|addr| seen.insert(*addr));
+        Ok(successors)
     }
 
     pub fn get_a(&self) -> u64 {
@@ -178,62 +290,259 @@ impl fmt::Debug for Inst<'_> {
         f.write_fmt(format_args!(
             "lenght={} data={:?}",
             self.length(),
-            &self.bytes[..self.length()]
+            self.bytes
         ))
     }
 }
 
 impl fmt::Display for Inst<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", INSTTYPES[self.op()].mnemonic)
+        write!(f, "{}", self.format(&NoResolver))
     }
 }
 
+/// Error raised on malformed bytecode. Offsets are code-unit addresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisassError {
+    /// The instruction needs more bytes than there are left in the method.
+    Truncated {
+        offset: usize,
+        needed: usize,
+        available: usize,
+    },
+    /// NOP with a payload identifier other than 0x01, 0x02 or 0x03.
+    UnknownPayload { offset: usize, ident: u8 },
+    /// Opcode marked as unused by the dex format.
+    InvalidOpcode { offset: usize, op: u8 },
+    /// Switch whose target is not a payload of the matching kind.
+    MissingPayload { offset: usize, target: usize },
+}
+
+impl DisassError {
+    pub fn offset(&self) -> usize {
+        match self {
+            DisassError::Truncated { offset, .. }
+            | DisassError::UnknownPayload { offset, .. }
+            | DisassError::InvalidOpcode { offset, .. }
+            | DisassError::MissingPayload { offset, .. } => *offset,
+        }
+    }
+}
+
+impl fmt::Display for DisassError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisassError::Truncated {
+                offset,
+                needed,
+                available,
+            } => write!(
+                f,
+                "Truncated instruction at {:#x}: {} bytes needed, {} available",
+                offset, needed, available
+            ),
+            DisassError::UnknownPayload { offset, ident } => {
+                write!(f, "Unexpected NOP type {:x} at {:#x}", ident, offset)
+            }
+            DisassError::InvalidOpcode { offset, op } => {
+                write!(f, "Invalid opcode {:#04x} at {:#x}", op, offset)
+            }
+            DisassError::MissingPayload { offset, target } => write!(
+                f,
+                "No payload at {:#x} for the instruction at {:#x}",
+                target, offset
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DisassError {}
+
+/// Iterates over the instructions of a method. By default the iteration
+/// stops after the first error; with recovery, it resumes at the next code
+/// unit.
 pub struct InstIterator<'a> {
     pub bytes: &'a [u8],
     index: usize,
     length: usize,
+    recover: bool,
 }
 
 impl InstIterator<'_> {
     pub fn new(bytes: &[u8], length: usize) -> InstIterator {
         InstIterator {
-            bytes: bytes,
+            bytes,
             index: 0,
-            length: length,
+            length: length.min(bytes.len()),
+            recover: false,
         }
     }
+
+    pub fn with_recovery(mut self) -> Self {
+        self.recover = true;
+        self
+    }
 }
 
 impl<'a> Iterator for InstIterator<'a> {
-    type Item = Inst<'a>;
-
-    fn next(&mut self) -> Option<Inst<'a>> {
-        if self.index < self.length {
-            let i = Inst {
-                bytes: &self.bytes[self.index..],
-            };
-            self.index += i.length();
-            Some(i)
-        } else {
-            None
+    type Item = Result<Inst<'a>, DisassError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.length {
+            return None;
+        }
+        match Inst::parse(&self.bytes[self.index..self.length], self.index / 2) {
+            Ok(i) => {
+                self.index += i.length();
+                Some(Ok(i))
+            }
+            Err(e) => {
+                self.index = if self.recover {
+                    self.index + 2
+                } else {
+                    self.length
+                };
+                Some(Err(e))
+            }
         }
     }
 }
 
-pub fn disassemble<'a>(code: &'a CodeItem) -> InstIterator<'a> {
+/// Bytes of the instructions of a method.
+pub fn code_bytes(code: &CodeItem) -> &[u8] {
     let (_, data, _) = unsafe { (code.insns()).align_to::<u8>() };
-    InstIterator::new(data, code.insns().len() * 2)
+    data
+}
+
+pub fn disassemble<'a>(code: &'a CodeItem) -> InstIterator<'a> {
+    InstIterator::new(code_bytes(code), code.insns().len() * 2)
 }
 
 #[cfg(test)]
 mod test_inst {
-    use super::Inst;
+    use super::{DisassError, Inst, InstIterator};
 
     #[test]
     fn test_op() {
         let buf = [0, 0];
-        let i = Inst { bytes: &buf };
+        let i = Inst {
+            bytes: &buf,
+            addr: 0,
+        };
         assert_eq!(i.op(), buf[0] as usize);
     }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Inst::parse(&[0x6e, 0x20, 0x01], 3).unwrap_err(),
+            DisassError::Truncated {
+                offset: 3,
+                needed: 6,
+                available: 3
+            }
+        );
+        assert_eq!(
+            Inst::parse(&[0x00, 0x07], 0).unwrap_err(),
+            DisassError::UnknownPayload {
+                offset: 0,
+                ident: 7
+            }
+        );
+        assert_eq!(
+            Inst::parse(&[0x3e, 0x00], 1).unwrap_err(),
+            DisassError::InvalidOpcode {
+                offset: 1,
+                op: 0x3e
+            }
+        );
+        // fill-array-data-payload of 3 bytes, padded to 12 bytes
+        let payload = [0x00, 0x03, 0x01, 0x00, 0x03, 0x00, 0x00, 0x00, 1, 2, 3, 0];
+        assert_eq!(Inst::parse(&payload, 0).unwrap().length(), 12);
+    }
+
+    #[test]
+    fn test_recovery() {
+        // nop, unused 0x3e, return-void, then a truncated const/16
+        let code = [0x00, 0x00, 0x3e, 0x00, 0x0e, 0x00, 0x13, 0x00];
+        let ops: Vec<Result<usize, usize>> = InstIterator::new(&code, code.len())
+            .with_recovery()
+            .map(|r| r.map(|i| i.op()).map_err(|e| e.offset()))
+            .collect();
+        assert_eq!(ops, vec![Ok(0x00), Err(1), Ok(0x0e), Err(3)]);
+
+        let ops: Vec<bool> = InstIterator::new(&code, code.len())
+            .map(|r| r.is_ok())
+            .collect();
+        assert_eq!(ops, vec![true, false]);
+    }
+
+    #[test]
+    fn test_branch_targets() {
+        // 0: if-eqz v0, +5
+        // 2: packed-switch v1, +6
+        // 5: goto -5
+        // 6: return-void
+        // 7: nop (padding)
+        // 8: packed-switch-payload, first key 0, targets +4 and +3
+        let code = [
+            0x38, 0x00, 0x05, 0x00, 0x2b, 0x01, 0x06, 0x00, 0x00, 0x00, 0x28, 0xfb, 0x0e, 0x00,
+            0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00,
+            0x03, 0x00, 0x00, 0x00,
+        ];
+        let insts: Vec<Inst> = InstIterator::new(&code, code.len())
+            .map(|i| i.unwrap())
+            .collect();
+        let addrs: Vec<usize> = insts.iter().map(|i| i.addr()).collect();
+        assert_eq!(addrs, vec![0, 2, 5, 6, 7, 8]);
+
+        assert_eq!(insts[0].branch_target(), Some(5));
+        assert_eq!(insts[0].successors(&code), Ok(vec![2, 5]));
+        assert_eq!(insts[1].payload_addr(), Some(8));
+        assert_eq!(insts[1].switch_targets(&code), Ok(vec![6, 5]));
+        assert_eq!(insts[1].successors(&code), Ok(vec![5, 6]));
+        assert_eq!(insts[2].branch_target(), Some(0));
+        assert_eq!(insts[2].successors(&code), Ok(vec![0]));
+        assert_eq!(insts[3].successors(&code), Ok(vec![]));
+        assert_eq!(insts[4].successors(&code), Ok(vec![8]));
+        assert_eq!(
+            insts[2].switch_targets(&code),
+            Err(DisassError::MissingPayload {
+                offset: 5,
+                target: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_backward_payloads() {
+        // 0: goto +20
+        // 1: nop (padding)
+        // 2: packed-switch-payload, first key 0, target +9
+        // 8: sparse-switch-payload, key 5, target +6
+        // 14: fill-array-data-payload of two shorts
+        // 20: packed-switch v0, -18
+        // 23: sparse-switch v0, -15
+        // 26: fill-array-data v1, -12
+        // 29: return-void
+        let code = [
+            0x28, 0x14, 0x00, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x09, 0x00,
+            0x00, 0x00, 0x00, 0x02, 0x01, 0x00, 0x05, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00,
+            0x00, 0x03, 0x02, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x2b, 0x00,
+            0xee, 0xff, 0xff, 0xff, 0x2c, 0x00, 0xf1, 0xff, 0xff, 0xff, 0x26, 0x01, 0xf4, 0xff,
+            0xff, 0xff, 0x0e, 0x00,
+        ];
+        let insts: Vec<Inst> = InstIterator::new(&code, code.len())
+            .map(|i| i.unwrap())
+            .collect();
+        let addrs: Vec<usize> = insts.iter().map(|i| i.addr()).collect();
+        assert_eq!(addrs, vec![0, 1, 2, 8, 14, 20, 23, 26, 29]);
+
+        assert_eq!(insts[5].payload_addr(), Some(2));
+        assert_eq!(insts[5].successors(&code), Ok(vec![23, 29]));
+        assert_eq!(insts[6].payload_addr(), Some(8));
+        assert_eq!(insts[6].successors(&code), Ok(vec![26, 29]));
+        assert_eq!(insts[7].payload_addr(), Some(14));
+        assert_eq!(insts[7].successors(&code), Ok(vec![29]));
+    }
 }
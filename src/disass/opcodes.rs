@@ -220,3 +220,5 @@ pub const INVOKEPOLYMORPHIC: usize = 0xfa;
 pub const INVOKEPOLYMORPHIC_RANGE: usize = 0xfb;
 pub const INVOKE_CUSTOM: usize = 0xfc;
 pub const INVOKE_CUSTOM_RANGE: usize = 0xfd;
+pub const CONSTMETHODHANDLE: usize = 0xfe;
+pub const CONSTMETHODTYPE: usize = 0xff;
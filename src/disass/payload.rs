@@ -0,0 +1,253 @@
+use crate::disass::opcodes::*;
+use crate::disass::{DisassError, Inst};
+
+/// Jump table of a `packed-switch`, whose keys are consecutive.
+///
+/// Targets are relative to the address of the switch instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedSwitchPayload {
+    pub first_key: i32,
+    pub targets: Vec<i32>,
+}
+
+/// Jump table of a `sparse-switch`, with sorted keys.
+///
+/// Targets are relative to the address of the switch instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseSwitchPayload {
+    pub keys: Vec<i32>,
+    pub targets: Vec<i32>,
+}
+
+/// Static initializer of an array, stored as `size` elements of
+/// `element_width` bytes in little endian.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FillArrayDataPayload {
+    pub element_width: u16,
+    pub size: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Payload {
+    PackedSwitch(PackedSwitchPayload),
+    SparseSwitch(SparseSwitchPayload),
+    FillArrayData(FillArrayDataPayload),
+}
+
+fn read_i32s(bytes: &[u8]) -> Vec<i32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+impl PackedSwitchPayload {
+    /// `(key, target)` pairs of the cases.
+    pub fn cases(&self) -> Vec<(i32, i32)> {
+        self.targets
+            .iter()
+            .enumerate()
+            .map(|(i, target)| (self.first_key.wrapping_add(i as i32), *target))
+            .collect()
+    }
+}
+
+impl SparseSwitchPayload {
+    /// `(key, target)` pairs of the cases.
+    pub fn cases(&self) -> Vec<(i32, i32)> {
+        self.keys
+            .iter()
+            .copied()
+            .zip(self.targets.iter().copied())
+            .collect()
+    }
+}
+
+impl FillArrayDataPayload {
+    /// Elements of the array, sign extended.
+    pub fn elements(&self) -> Vec<i64> {
+        let width = self.element_width as usize;
+        if width == 0 {
+            return Vec::new();
+        }
+        self.data
+            .chunks_exact(width)
+            .map(|b| match width {
+                1 => b[0] as i8 as i64,
+                2 => i16::from_le_bytes([b[0], b[1]]) as i64,
+                4 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as i64,
+                _ => {
+                    let mut bytes = [0u8; 8];
+                    bytes[..width.min(8)].copy_from_slice(&b[..width.min(8)]);
+                    i64::from_le_bytes(bytes)
+                }
+            })
+            .collect()
+    }
+}
+
+impl Payload {
+    /// `(key, target)` pairs of a switch payload, or an empty list for array
+    /// data.
+    pub fn cases(&self) -> Vec<(i32, i32)> {
+        match self {
+            Payload::PackedSwitch(payload) => payload.cases(),
+            Payload::SparseSwitch(payload) => payload.cases(),
+            Payload::FillArrayData(_) => Vec::new(),
+        }
+    }
+}
+
+impl Inst<'_> {
+    /// Decodes the pseudo-instruction if it is a payload.
+    pub fn payload(&self) -> Option<Payload> {
+        if self.op() != NOP || self.bytes[1] == 0 {
+            return None;
+        }
+        let b = self.bytes;
+        let size = u16::from_le_bytes([b[2], b[3]]) as usize;
+        let payload = match b[1] {
+            1 => Payload::PackedSwitch(PackedSwitchPayload {
+                first_key: i32::from_le_bytes([b[4], b[5], b[6], b[7]]),
+                targets: read_i32s(&b[8..8 + 4 * size]),
+            }),
+            2 => Payload::SparseSwitch(SparseSwitchPayload {
+                keys: read_i32s(&b[4..4 + 4 * size]),
+                targets: read_i32s(&b[4 + 4 * size..4 + 8 * size]),
+            }),
+            _ => {
+                let count = u32::from_le_bytes([b[4], b[5], b[6], b[7]]);
+                Payload::FillArrayData(FillArrayDataPayload {
+                    element_width: size as u16,
+                    size: count,
+                    data: b[8..8 + size * count as usize].to_vec(),
+                })
+            }
+        };
+        Some(payload)
+    }
+
+    /// Payload of a switch or `fill-array-data` instruction, read from
+    /// `code`, the bytes of the whole method.
+    pub fn linked_payload(&self, code: &[u8]) -> Result<Payload, DisassError> {
+        let target = self.payload_addr();
+        let missing = DisassError::MissingPayload {
+            offset: self.addr,
+            target: target.unwrap_or(0),
+        };
+        let ident = match self.op() {
+            PACKEDSWITCH => 1,
+            SPARSESWITCH => 2,
+            FILLARRAYDATA => 3,
+            _ => return Err(missing),
+        };
+        let start = match target {
+            Some(addr) if addr * 2 < code.len() => addr * 2,
+            _ => return Err(missing),
+        };
+        let payload = Inst::parse(&code[start..], start / 2)?;
+        if payload.op() != NOP || payload.bytes[1] != ident {
+            return Err(missing);
+        }
+        Ok(payload.payload().unwrap())
+    }
+
+    /// Case keys and absolute targets of a switch instruction.
+    pub fn switch_cases(&self, code: &[u8]) -> Result<Vec<(i32, usize)>, DisassError> {
+        if !self.is_switch() {
+            return Err(DisassError::MissingPayload {
+                offset: self.addr,
+                target: 0,
+            });
+        }
+        let cases = self
+            .linked_payload(code)?
+            .cases()
+            .into_iter()
+            .filter_map(|(key, offset)| Some((key, self.relative(offset as u64)?)))
+            .collect();
+        Ok(cases)
+    }
+}
+
+#[cfg(test)]
+mod test_payload {
+    use super::*;
+
+    fn payload(bytes: &[u8]) -> Payload {
+        Inst::parse(bytes, 0).unwrap().payload().unwrap()
+    }
+
+    #[test]
+    fn test_switch_payloads() {
+        let packed = [
+            0x00, 0x01, 0x02, 0x00, 0xff, 0xff, 0xff, 0xff, 4, 0, 0, 0, 6, 0, 0, 0,
+        ];
+        assert_eq!(
+            payload(&packed),
+            Payload::PackedSwitch(PackedSwitchPayload {
+                first_key: -1,
+                targets: vec![4, 6]
+            })
+        );
+        assert_eq!(payload(&packed).cases(), vec![(-1, 4), (0, 6)]);
+
+        let sparse = [
+            0x00, 0x02, 0x02, 0x00, 3, 0, 0, 0, 10, 0, 0, 0, 0xfe, 0xff, 0xff, 0xff, 8, 0, 0, 0,
+        ];
+        assert_eq!(payload(&sparse).cases(), vec![(3, -2), (10, 8)]);
+    }
+
+    #[test]
+    fn test_fill_array_data() {
+        let bytes = [
+            0x00, 0x03, 0x02, 0x00, 0x03, 0x00, 0x00, 0x00, 1, 0, 0xff, 0xff, 7, 0,
+        ];
+        match payload(&bytes) {
+            Payload::FillArrayData(array) => {
+                assert_eq!(array.element_width, 2);
+                assert_eq!(array.size, 3);
+                assert_eq!(array.elements(), vec![1, -1, 7]);
+            }
+            other => panic!("Unexpected payload {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_linked_payload() {
+        // 0: fill-array-data v0, +4
+        // 3: nop
+        // 4: fill-array-data-payload of 2 bytes
+        let code = [
+            0x26, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x01, 0x00, 0x02, 0x00,
+            0x00, 0x00, 0x41, 0x42,
+        ];
+        let inst = Inst::parse(&code, 0).unwrap();
+        match inst.linked_payload(&code) {
+            Ok(Payload::FillArrayData(array)) => assert_eq!(array.data, b"AB"),
+            other => panic!("Unexpected payload {:?}", other),
+        }
+        assert!(inst.switch_cases(&code).is_err());
+    }
+
+    #[test]
+    fn test_backward_linked_payload() {
+        // 0: sparse-switch-payload, key 7, target +3
+        // 6: sparse-switch v0, -6
+        // 9: return-void
+        let code = [
+            0x00, 0x02, 0x01, 0x00, 0x07, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x2c, 0x00,
+            0xfa, 0xff, 0xff, 0xff, 0x0e, 0x00,
+        ];
+        let inst = Inst::parse(&code[12..], 6).unwrap();
+        assert_eq!(
+            inst.linked_payload(&code),
+            Ok(Payload::SparseSwitch(SparseSwitchPayload {
+                keys: vec![7],
+                targets: vec![3]
+            }))
+        );
+        assert_eq!(inst.switch_cases(&code), Ok(vec![(7, 9)]));
+    }
+}
@@ -0,0 +1,112 @@
+use crate::disass::{Inst, Reg, INSTTYPES};
+
+impl Inst<'_> {
+    /// Registers of the `regs` column of the instruction table marked with
+    /// `single`, or with its uppercase for wide register pairs.
+    fn registers(&self, single: char) -> Vec<Reg> {
+        let wide = single.to_ascii_uppercase();
+        let mut regs = Vec::new();
+        for token in INSTTYPES[self.op()].regs.split_whitespace() {
+            match token {
+                "args" | "range" if single != 'u' => {}
+                "args" => regs.extend(self.arg_list()),
+                "range" => regs.extend(self.arg_range()),
+                _ => {
+                    let mut chars = token.chars();
+                    let (kind, operand) = match (chars.next(), chars.next()) {
+                        (Some(kind), Some(operand)) => (kind, operand),
+                        _ => continue,
+                    };
+                    let reg = self.operand(operand) as Reg;
+                    if kind == single {
+                        regs.push(reg);
+                    } else if kind == wide {
+                        // A pair starting at the last register has no second half
+                        regs.push(reg);
+                        regs.extend(reg.checked_add(1));
+                    }
+                }
+            }
+        }
+        regs
+    }
+
+    /// Registers written by the instruction. Wide values define both
+    /// registers of their pair.
+    pub fn defs(&self) -> Vec<Reg> {
+        self.registers('d')
+    }
+
+    /// Registers read by the instruction, in operand order. Wide values use
+    /// both registers of their pair, and invokes use every argument register.
+    pub fn uses(&self) -> Vec<Reg> {
+        let mut regs = self.registers('u');
+        let mut seen = std::collections::HashSet::new();
+        regs.retain(|reg| seen.insert(*reg));
+        regs
+    }
+}
+
+#[cfg(test)]
+mod test_regs {
+    use crate::disass::{Inst, INSTTYPES};
+
+    fn inst(bytes: &[u8]) -> Inst<'_> {
+        Inst::parse(bytes, 0).unwrap()
+    }
+
+    #[test]
+    fn test_defs_and_uses() {
+        // move-wide v2, v4
+        let i = inst(&[0x04, 0x42]);
+        assert_eq!(i.defs(), vec![2, 3]);
+        assert_eq!(i.uses(), vec![4, 5]);
+        // shl-long v0, v2, v4
+        let i = inst(&[0xa3, 0x00, 0x02, 0x04]);
+        assert_eq!(i.defs(), vec![0, 1]);
+        assert_eq!(i.uses(), vec![2, 3, 4]);
+        // add-int/2addr v1, v2
+        let i = inst(&[0xb0, 0x21]);
+        assert_eq!(i.defs(), vec![1]);
+        assert_eq!(i.uses(), vec![1, 2]);
+        // aput-object v0, v1, v2
+        let i = inst(&[0x4d, 0x00, 0x01, 0x02]);
+        assert!(i.defs().is_empty());
+        assert_eq!(i.uses(), vec![0, 1, 2]);
+        // return-void
+        let i = inst(&[0x0e, 0x00]);
+        assert!(i.defs().is_empty() && i.uses().is_empty());
+        // move-wide/16 v65535, v0
+        let i = inst(&[0x06, 0x00, 0xff, 0xff, 0x00, 0x00]);
+        assert_eq!(i.defs(), vec![65535]);
+        assert_eq!(i.uses(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_invokes() {
+        // invoke-virtual {v1, v2, v3}, meth@0x0010
+        let i = inst(&[0x6e, 0x30, 0x10, 0x00, 0x21, 0x03]);
+        assert!(i.defs().is_empty());
+        assert_eq!(i.uses(), vec![1, 2, 3]);
+        // invoke-static/range {v4 .. v6}, meth@0x0020
+        let i = inst(&[0x77, 0x03, 0x20, 0x00, 0x04, 0x00]);
+        assert_eq!(i.uses(), vec![4, 5, 6]);
+        // move-result-wide v6
+        let i = inst(&[0x0b, 0x06]);
+        assert_eq!(i.defs(), vec![6, 7]);
+    }
+
+    #[test]
+    fn test_table() {
+        for inst_type in INSTTYPES.iter() {
+            for token in inst_type.regs.split_whitespace() {
+                let valid = token == "args"
+                    || token == "range"
+                    || (token.len() == 2
+                        && "dDuU".contains(&token[..1])
+                        && "ABC".contains(&token[1..]));
+                assert!(valid, "{}: bad regs token {}", inst_type.mnemonic, token);
+            }
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use dex::code::{CodeItem, ExceptionType};
+
+/// Exception caught by a handler.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CatchType {
+    /// `catch (Throwable)` or `finally` handler.
+    All,
+    /// Type descriptor of the caught exception class.
+    Type(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Handler {
+    pub catch: CatchType,
+    pub addr: usize,
+}
+
+/// Code-unit range `[start, end)` covered by a try block, with its handlers
+/// in the order they are tried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TryBlock {
+    pub start: usize,
+    pub end: usize,
+    pub handlers: Vec<Handler>,
+}
+
+impl TryBlock {
+    pub fn covers(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end
+    }
+}
+
+/// Try blocks and catch handlers of a method.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ExceptionTable {
+    blocks: Vec<TryBlock>,
+}
+
+impl ExceptionTable {
+    pub fn new(blocks: Vec<TryBlock>) -> Self {
+        ExceptionTable { blocks }
+    }
+
+    pub fn from_code(code: &CodeItem) -> Self {
+        let blocks = code
+            .tries()
+            .try_catch_blocks()
+            .iter()
+            .map(|block| TryBlock {
+                start: block.start_addr() as usize,
+                end: block.start_addr() as usize + block.insn_count() as usize,
+                handlers: block
+                    .catch_handlers()
+                    .iter()
+                    .map(|handler| Handler {
+                        catch: match handler.exception() {
+                            ExceptionType::BaseException => CatchType::All,
+                            ExceptionType::Ty(jtype) => {
+                                CatchType::Type(jtype.type_descriptor().to_string())
+                            }
+                        },
+                        addr: handler.addr() as usize,
+                    })
+                    .collect(),
+            })
+            .collect();
+        ExceptionTable { blocks }
+    }
+
+    pub fn blocks(&self) -> &[TryBlock] {
+        &self.blocks
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Try blocks covering the instruction at `addr`.
+    pub fn covering(&self, addr: usize) -> impl Iterator<Item = &TryBlock> + '_ {
+        self.blocks.iter().filter(move |block| block.covers(addr))
+    }
+
+    /// Handlers that may catch an exception thrown at `addr`.
+    pub fn handlers_at(&self, addr: usize) -> Vec<&Handler> {
+        self.covering(addr)
+            .flat_map(|block| block.handlers.iter())
+            .collect()
+    }
+
+    /// Entry addresses of all the handlers, without duplicates.
+    pub fn handler_addrs(&self) -> Vec<usize> {
+        let mut addrs: Vec<usize> = self
+            .blocks
+            .iter()
+            .flat_map(|block| block.handlers.iter().map(|handler| handler.addr))
+            .collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        addrs
+    }
+}
+
+#[cfg(test)]
+mod test_tries {
+    use super::*;
+
+    #[test]
+    fn test_handlers_at() {
+        let io = Handler {
+            catch: CatchType::Type(String::from("Ljava/io/IOException;")),
+            addr: 10,
+        };
+        let all = Handler {
+            catch: CatchType::All,
+            addr: 14,
+        };
+        let table = ExceptionTable::new(vec![
+            TryBlock {
+                start: 0,
+                end: 4,
+                handlers: vec![io.clone(), all.clone()],
+            },
+            TryBlock {
+                start: 4,
+                end: 8,
+                handlers: vec![all.clone()],
+            },
+        ]);
+        assert_eq!(table.handlers_at(3), vec![&io, &all]);
+        assert_eq!(table.handlers_at(4), vec![&all]);
+        assert!(table.handlers_at(8).is_empty());
+        assert_eq!(table.covering(5).count(), 1);
+        assert_eq!(table.handler_addrs(), vec![10, 14]);
+    }
+}
@@ -0,0 +1,322 @@
+use crate::api::{ApiClassifier, CalleeKind, FrameworkApis};
+use crate::apk::{Apk, DexFile, DexLoadError};
+use crate::callgraph::{get_invoked_methods, method_name};
+use crate::package::PackageTree;
+use std::collections::{btree_map, BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+/// Multiset of the APIs invoked by a piece of code, keyed by `Lclass;->name`.
+///
+/// Only calls to framework methods are recorded, so renaming the classes of
+/// a library leaves its vector untouched.
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ApiVector {
+    calls: BTreeMap<String, usize>,
+}
+
+#[derive(Debug)]
+struct CompactVectorError {
+    entry: String,
+}
+
+impl fmt::Display for CompactVectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!("Invalid api vector entry {:?}", self.entry))
+    }
+}
+
+impl std::error::Error for CompactVectorError {}
+
+pub(crate) const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+pub(crate) fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |h, b| (h ^ u64::from(*b)).wrapping_mul(FNV_PRIME))
+}
+
+impl ApiVector {
+    pub fn new() -> Self {
+        ApiVector::default()
+    }
+
+    pub fn add(&mut self, api: &str, count: usize) {
+        if count > 0 {
+            *self.calls.entry(String::from(api)).or_insert(0) += count;
+        }
+    }
+
+    pub fn merge(&mut self, other: &ApiVector) {
+        for (api, count) in &other.calls {
+            self.add(api, *count);
+        }
+    }
+
+    /// Number of times `api` is invoked.
+    pub fn get(&self, api: &str) -> usize {
+        self.calls.get(api).copied().unwrap_or(0)
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<'_, String, usize> {
+        self.calls.iter()
+    }
+
+    /// Number of distinct APIs.
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Number of invocations, counting repetitions.
+    pub fn total(&self) -> usize {
+        self.calls.values().sum()
+    }
+
+    /// 64-bit FNV-1a hash of the multiset. Unlike `std::hash::Hash` it does
+    /// not change between runs or builds, so it can be stored on disk.
+    pub fn stable_hash(&self) -> u64 {
+        self.calls.iter().fold(FNV_OFFSET, |h, (api, count)| {
+            let h = fnv1a(h, api.as_bytes());
+            let h = fnv1a(h, b"=");
+            let h = fnv1a(h, count.to_string().as_bytes());
+            fnv1a(h, b",")
+        })
+    }
+
+    /// Serializes the vector as `api=count` entries separated by commas, in
+    /// the same order used by `stable_hash`.
+    pub fn to_compact(&self) -> String {
+        self.calls
+            .iter()
+            .map(|(api, count)| format!("{}={}", api, count))
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+
+    pub fn from_compact(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut v = ApiVector::new();
+        for entry in s.split(',').filter(|e| !e.is_empty()) {
+            let parsed = entry
+                .rfind('=')
+                .and_then(|i| entry[i + 1..].parse().ok().map(|n| (&entry[..i], n)));
+            match parsed {
+                Some((api, count)) if !api.is_empty() => v.add(api, count),
+                _ => {
+                    return Err(Box::new(CompactVectorError {
+                        entry: String::from(entry),
+                    }))
+                }
+            }
+        }
+        Ok(v)
+    }
+}
+
+impl<'a> IntoIterator for &'a ApiVector {
+    type Item = (&'a String, &'a usize);
+    type IntoIter = btree_map::Iter<'a, String, usize>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// API usage of a package subtree: every class under `package` and the APIs
+/// they invoke.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageFeature {
+    pub package: String,
+    pub classes: usize,
+    pub methods: usize,
+    /// Class names relative to `package`, e.g. `internal/Util;`.
+    pub class_names: BTreeSet<String>,
+    pub api_calls: ApiVector,
+}
+
+/// Returns the package of a class type descriptor, keeping the trailing slash:
+/// `Lcom/foo/Bar;` becomes `Lcom/foo/`. Classes in the default package yield `L`.
+pub fn package_of(class_name: &str) -> &str {
+    match class_name.rfind('/') {
+        Some(i) => &class_name[..=i],
+        None => "L",
+    }
+}
+
+/// Computes the API vector of every class in `dex_files`. Only the callees
+/// that `apis` classifies as framework methods are counted.
+pub fn class_api_vectors(
+    dex_files: &[&DexFile],
+    apis: &FrameworkApis,
+) -> Result<HashMap<String, ApiVector>, Box<dyn std::error::Error>> {
+    let classifier = ApiClassifier::from_dex_files(apis, dex_files)?;
+    let mut vectors: HashMap<String, ApiVector> = HashMap::new();
+    for &dex in dex_files {
+        for class in dex.classes() {
+            let class = class?;
+            // The first definition of a class wins, as in the package tree
+            let name = class.jtype().type_descriptor().to_string();
+            if vectors.contains_key(&name) {
+                continue;
+            }
+            let mut v = ApiVector::new();
+            for method in class.methods() {
+                if let Some(code) = method.code() {
+                    for target in get_invoked_methods(code, dex) {
+                        if classifier.classify(&target, dex) == Some(CalleeKind::Framework) {
+                            if let Some(name) = method_name(&target, dex) {
+                                v.add(&name, 1);
+                            }
+                        }
+                    }
+                }
+            }
+            vectors.insert(name, v);
+        }
+    }
+    Ok(vectors)
+}
+
+/// Aggregates the class vectors over every package of the tree except the
+/// root, which stands for the whole app.
+pub fn tree_features(
+    tree: &PackageTree,
+    vectors: &HashMap<String, ApiVector>,
+) -> Vec<PackageFeature> {
+    tree.pre_order()
+        .skip(1)
+        .map(|node| {
+            let mut api_calls = ApiVector::new();
+            let mut class_names = BTreeSet::new();
+            for class in node.all_classes() {
+                if let Some(v) = vectors.get(&class.name) {
+                    api_calls.merge(v);
+                }
+                class_names.insert(String::from(&class.name[node.name.len()..]));
+            }
+            PackageFeature {
+                package: node.name.clone(),
+                classes: node.class_count,
+                methods: node.method_count,
+                class_names,
+                api_calls,
+            }
+        })
+        .collect()
+}
+
+/// Computes the feature of every package subtree in the apk. Fails if any
+/// of its dex files cannot be loaded.
+pub fn package_features(
+    apk: &Apk,
+    apis: &FrameworkApis,
+) -> Result<Vec<PackageFeature>, Box<dyn std::error::Error>> {
+    let dex_files: Vec<(usize, &DexFile)> = apk.dex_files()?.into_iter().enumerate().collect();
+    dex_package_features(&dex_files, apis)
+}
+
+/// Like `package_features`, but skips the dex files that cannot be loaded and
+/// returns their errors. Fails only when none of them can be loaded.
+pub fn package_features_partial(
+    apk: &Apk,
+    apis: &FrameworkApis,
+) -> Result<(Vec<PackageFeature>, Vec<DexLoadError>), Box<dyn std::error::Error>> {
+    let (dex_files, mut errors) = apk.dex_files_partial();
+    if dex_files.is_empty() && !errors.is_empty() {
+        return Err(Box::new(errors.swap_remove(0)));
+    }
+    Ok((dex_package_features(&dex_files, apis)?, errors))
+}
+
+/// Computes the feature of every package subtree of `dex_files`, given with
+/// their index in `Apk::dex_names`.
+pub fn dex_package_features(
+    dex_files: &[(usize, &DexFile)],
+    apis: &FrameworkApis,
+) -> Result<Vec<PackageFeature>, Box<dyn std::error::Error>> {
+    let tree = PackageTree::from_dex_files(dex_files)?;
+    let dex: Vec<&DexFile> = dex_files.iter().map(|&(_, dex)| dex).collect();
+    Ok(tree_features(&tree, &class_api_vectors(&dex, apis)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::ClassInfo;
+
+    fn vector(apis: &[(&str, usize)]) -> ApiVector {
+        let mut v = ApiVector::new();
+        for (api, count) in apis {
+            v.add(api, *count);
+        }
+        v
+    }
+
+    #[test]
+    fn test_package_of() {
+        assert_eq!(package_of("Lcom/foo/Bar;"), "Lcom/foo/");
+        assert_eq!(package_of("LBar;"), "L");
+    }
+
+    #[test]
+    fn test_stable_hash() {
+        let a = vector(&[
+            ("Ljava/io/File;->delete", 1),
+            ("Ljava/lang/String;->equals", 2),
+        ]);
+        let b = vector(&[
+            ("Ljava/lang/String;->equals", 2),
+            ("Ljava/io/File;->delete", 1),
+        ]);
+        let c = vector(&[
+            ("Ljava/lang/String;->equals", 1),
+            ("Ljava/io/File;->delete", 1),
+        ]);
+        assert_eq!(a.stable_hash(), b.stable_hash());
+        assert_ne!(a.stable_hash(), c.stable_hash());
+        assert_eq!(ApiVector::new().stable_hash(), FNV_OFFSET);
+    }
+
+    #[test]
+    fn test_compact() -> Result<(), Box<dyn std::error::Error>> {
+        let v = vector(&[
+            ("Ljava/io/File;->delete", 1),
+            ("Ljava/lang/String;->equals", 2),
+        ]);
+        let s = v.to_compact();
+        assert_eq!(s, "Ljava/io/File;->delete=1,Ljava/lang/String;->equals=2");
+        assert_eq!(ApiVector::from_compact(&s)?, v);
+        assert!(ApiVector::from_compact("").is_ok());
+        assert!(ApiVector::from_compact("Ljava/io/File;->delete").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_renamed_packages_match() {
+        let mut vectors = HashMap::new();
+        let mut tree = PackageTree::new();
+        for name in &["Lokhttp3/Call;", "La/b;"] {
+            vectors.insert(
+                String::from(*name),
+                vector(&[("Ljava/net/Socket;->connect", 2)]),
+            );
+            tree.insert(ClassInfo {
+                name: String::from(*name),
+                dex: 0,
+                methods: 1,
+                instructions: 4,
+                invokes: 2,
+            });
+        }
+        let features = tree_features(&tree, &vectors);
+        assert_eq!(features.len(), 2);
+        assert_eq!(features[0].package, "La/");
+        assert_eq!(features[1].package, "Lokhttp3/");
+        assert_eq!(features[0].api_calls, features[1].api_calls);
+        assert!(features[0].class_names.contains("b;"));
+        assert!(features[1].class_names.contains("Call;"));
+    }
+}
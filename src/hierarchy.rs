@@ -0,0 +1,316 @@
+use crate::apk::DexFile;
+use dex::method::Method;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+const ACC_INTERFACE: u32 = 0x200;
+const ACC_ABSTRACT: u32 = 0x400;
+
+/// Class defined in the APK.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ClassNode {
+    pub super_class: Option<String>,
+    pub interfaces: Vec<String>,
+    /// Methods defined by the class, as `name(params)return`.
+    pub methods: HashSet<String>,
+    pub is_interface: bool,
+    pub is_abstract: bool,
+}
+
+impl ClassNode {
+    /// Whether the class can be instantiated.
+    pub fn is_concrete(&self) -> bool {
+        !self.is_interface && !self.is_abstract
+    }
+}
+
+/// Superclasses and interfaces of the classes of all the dex files of an
+/// APK. Supertypes may be classes of the framework that are not defined in
+/// the APK.
+#[derive(Debug, Clone, Default)]
+pub struct ClassHierarchy {
+    classes: HashMap<String, ClassNode>,
+    /// Direct subclasses and implementations of every supertype.
+    subtypes: HashMap<String, Vec<String>>,
+}
+
+/// Member name of `method` in the `name(params)return` form.
+pub fn member_name(method: &Method) -> String {
+    let params: Vec<String> = method
+        .params()
+        .iter()
+        .map(|param| param.type_descriptor().to_string())
+        .collect();
+    format!(
+        "{}({}){}",
+        method.name(),
+        params.concat(),
+        method.return_type().type_descriptor()
+    )
+}
+
+/// Type descriptor of a binary class name, e.g. `La/b/C;` for `a.b.C`.
+pub fn class_descriptor(name: &str) -> String {
+    let name = name.replace('.', "/");
+    if name.starts_with('[') {
+        name
+    } else {
+        format!("L{};", name)
+    }
+}
+
+fn type_descriptor(dex: &DexFile, idx: u32) -> Option<String> {
+    Some(dex.get_type(idx).ok()?.type_descriptor().to_string())
+}
+
+impl ClassHierarchy {
+    /// As with the runtime class loader, the first dex file defining a class
+    /// wins.
+    pub fn from_dex_files(dex_files: &[&DexFile]) -> Self {
+        let mut hierarchy = ClassHierarchy::default();
+        for dex in dex_files {
+            for class in dex.classes().flatten() {
+                let name = class.jtype().type_descriptor().to_string();
+                if hierarchy.contains(&name) {
+                    continue;
+                }
+                let flags = class.access_flags().bits();
+                let node = ClassNode {
+                    super_class: class.super_class().and_then(|id| type_descriptor(dex, id)),
+                    interfaces: class
+                        .interfaces()
+                        .iter()
+                        .map(|interface| interface.type_descriptor().to_string())
+                        .collect(),
+                    methods: class.methods().map(member_name).collect(),
+                    is_interface: flags & ACC_INTERFACE != 0,
+                    is_abstract: flags & ACC_ABSTRACT != 0,
+                };
+                hierarchy.insert(name, node);
+            }
+        }
+        hierarchy
+    }
+
+    pub fn insert(&mut self, name: String, node: ClassNode) {
+        for supertype in node.super_class.iter().chain(node.interfaces.iter()) {
+            self.subtypes
+                .entry(supertype.clone())
+                .or_default()
+                .push(name.clone());
+        }
+        self.classes.insert(name, node);
+    }
+
+    pub fn get(&self, class: &str) -> Option<&ClassNode> {
+        self.classes.get(class)
+    }
+
+    pub fn contains(&self, class: &str) -> bool {
+        self.classes.contains_key(class)
+    }
+
+    pub fn classes(&self) -> impl Iterator<Item = (&String, &ClassNode)> + '_ {
+        self.classes.iter()
+    }
+
+    /// Classes defined in the APK that extend or implement `class`, directly
+    /// or not, including `class` itself when it is defined.
+    pub fn subtypes(&self, class: &str) -> BTreeSet<String> {
+        let mut found = BTreeSet::new();
+        if self.contains(class) {
+            found.insert(class.to_string());
+        }
+        let mut stack = vec![class];
+        while let Some(class) = stack.pop() {
+            for subtype in self.subtypes.get(class).into_iter().flatten() {
+                if found.insert(subtype.clone()) {
+                    stack.push(subtype);
+                }
+            }
+        }
+        found
+    }
+
+    /// Whether `class` is `supertype` or extends or implements it.
+    pub fn is_subtype(&self, class: &str, supertype: &str) -> bool {
+        class == supertype || self.subtypes(supertype).contains(class)
+    }
+
+    /// Class defining `member` for an object of type `class`: `class` or its
+    /// closest superclass defining it, then its interfaces for default
+    /// methods.
+    pub fn resolve_method<'a>(&'a self, class: &'a str, member: &str) -> Option<&'a str> {
+        let mut interfaces = Vec::new();
+        let mut current = Some(class);
+        // The bound protects against cycles in malformed dex files
+        for _ in 0..=self.classes.len() {
+            let name = match current {
+                Some(name) => name,
+                None => break,
+            };
+            // Framework superclasses are not known, but their subclasses may
+            // still get default methods from their interfaces
+            let node = match self.classes.get(name) {
+                Some(node) => node,
+                None => break,
+            };
+            if node.methods.contains(member) {
+                return Some(name);
+            }
+            interfaces.extend(node.interfaces.iter().map(String::as_str));
+            current = node.super_class.as_deref();
+        }
+        let mut seen = HashSet::new();
+        while let Some(interface) = interfaces.pop() {
+            if !seen.insert(interface) {
+                continue;
+            }
+            if let Some(node) = self.classes.get(interface) {
+                if node.methods.contains(member) {
+                    return Some(interface);
+                }
+                interfaces.extend(node.interfaces.iter().map(String::as_str));
+            }
+        }
+        None
+    }
+
+    /// Methods named `name` that `class` defines or inherits from its
+    /// superclasses, as `Lclass;->name(params)return`, sorted. Overridden
+    /// methods are only listed once, for the closest class.
+    pub fn methods_named(&self, class: &str, name: &str) -> Vec<String> {
+        let prefix = format!("{}(", name);
+        let mut members = HashSet::new();
+        let mut methods = Vec::new();
+        let mut current = Some(class);
+        // The bound protects against cycles in malformed dex files
+        for _ in 0..=self.classes.len() {
+            let (class, node) = match current.and_then(|c| Some((c, self.classes.get(c)?))) {
+                Some(found) => found,
+                None => break,
+            };
+            for member in &node.methods {
+                if member.starts_with(&prefix) && members.insert(member) {
+                    methods.push(format!("{}->{}", class, member));
+                }
+            }
+            current = node.super_class.as_deref();
+        }
+        methods.sort();
+        methods
+    }
+
+    /// Methods, in the `Lclass;->name(params)return` form, that a virtual or
+    /// interface call to `class->member` may run.
+    ///
+    /// With `instantiated`, only the classes in the set are considered as
+    /// receivers (Rapid Type Analysis); otherwise every concrete subtype is
+    /// (Class Hierarchy Analysis).
+    pub fn dispatch_targets(
+        &self,
+        class: &str,
+        member: &str,
+        instantiated: Option<&HashSet<String>>,
+    ) -> BTreeSet<String> {
+        let mut receivers: Vec<String> = self
+            .subtypes(class)
+            .into_iter()
+            .filter(|subtype| self.classes[subtype].is_concrete())
+            .collect();
+        if let Some(instantiated) = instantiated {
+            receivers.retain(|receiver| instantiated.contains(receiver));
+        }
+        receivers
+            .iter()
+            .filter_map(|receiver| self.resolve_method(receiver, member))
+            .map(|defining| format!("{}->{}", defining, member))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(super_class: &str, interfaces: &[&str], methods: &[&str]) -> ClassNode {
+        ClassNode {
+            super_class: Some(super_class.to_string()),
+            interfaces: interfaces.iter().map(|s| s.to_string()).collect(),
+            methods: methods.iter().map(|s| s.to_string()).collect(),
+            ..ClassNode::default()
+        }
+    }
+
+    fn hierarchy() -> ClassHierarchy {
+        let mut hierarchy = ClassHierarchy::default();
+        hierarchy.insert(
+            String::from("LBase;"),
+            ClassNode {
+                is_abstract: true,
+                ..node("Ljava/lang/Object;", &["Ljava/lang/Runnable;"], &["run()V"])
+            },
+        );
+        hierarchy.insert(
+            String::from("LFast;"),
+            node("LBase;", &[], &["run()V", "<init>()V"]),
+        );
+        hierarchy.insert(String::from("LSlow;"), node("LBase;", &[], &["<init>()V"]));
+        hierarchy.insert(
+            String::from("LTask;"),
+            node("Ljava/lang/Object;", &["Ljava/lang/Runnable;"], &["run()V"]),
+        );
+        hierarchy.insert(
+            String::from("LLogging;"),
+            ClassNode {
+                is_interface: true,
+                ..node("Ljava/lang/Object;", &[], &["log()V"])
+            },
+        );
+        hierarchy.insert(
+            String::from("LMain;"),
+            node("Landroid/app/Activity;", &["LLogging;"], &[]),
+        );
+        hierarchy
+    }
+
+    #[test]
+    fn test_subtypes() {
+        let hierarchy = hierarchy();
+        let subtypes: Vec<String> = hierarchy
+            .subtypes("Ljava/lang/Runnable;")
+            .into_iter()
+            .collect();
+        assert_eq!(subtypes, vec!["LBase;", "LFast;", "LSlow;", "LTask;"]);
+        assert!(hierarchy.is_subtype("LSlow;", "LBase;"));
+        assert!(!hierarchy.is_subtype("LTask;", "LBase;"));
+        assert_eq!(hierarchy.resolve_method("LSlow;", "run()V"), Some("LBase;"));
+        assert_eq!(hierarchy.resolve_method("LSlow;", "stop()V"), None);
+        assert_eq!(
+            hierarchy.resolve_method("LMain;", "log()V"),
+            Some("LLogging;")
+        );
+        assert_eq!(hierarchy.methods_named("LSlow;", "run"), ["LBase;->run()V"]);
+        assert_eq!(hierarchy.methods_named("LFast;", "run"), ["LFast;->run()V"]);
+        assert!(hierarchy.methods_named("LSlow;", "stop").is_empty());
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let hierarchy = hierarchy();
+        let cha: Vec<String> = hierarchy
+            .dispatch_targets("Ljava/lang/Runnable;", "run()V", None)
+            .into_iter()
+            .collect();
+        assert_eq!(
+            cha,
+            vec!["LBase;->run()V", "LFast;->run()V", "LTask;->run()V"]
+        );
+
+        let instantiated: HashSet<String> = vec![String::from("LFast;")].into_iter().collect();
+        let rta: Vec<String> = hierarchy
+            .dispatch_targets("LBase;", "run()V", Some(&instantiated))
+            .into_iter()
+            .collect();
+        assert_eq!(rta, vec!["LFast;->run()V"]);
+    }
+}
@@ -1,3 +1,17 @@
+pub mod api;
 pub mod apk;
+pub mod axml;
 pub mod callgraph;
+pub mod cfg;
+pub mod database;
+pub mod dataflow;
+pub mod detection;
 pub mod disass;
+pub mod features;
+pub mod hierarchy;
+pub mod manifest;
+pub mod options;
+pub mod package;
+pub mod signature;
+pub mod similarity;
+pub mod smali;
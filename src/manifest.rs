@@ -0,0 +1,483 @@
+use crate::axml::{self, AttributeValue, AxmlError, Element};
+use crate::hierarchy::{class_descriptor, ClassHierarchy};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ComponentKind {
+    Activity,
+    Service,
+    Receiver,
+    Provider,
+}
+
+impl ComponentKind {
+    fn tag(self) -> &'static str {
+        match self {
+            ComponentKind::Activity => "activity",
+            ComponentKind::Service => "service",
+            ComponentKind::Receiver => "receiver",
+            ComponentKind::Provider => "provider",
+        }
+    }
+
+    /// Methods the framework calls on a component, as `name(params)return`.
+    pub fn callbacks(self) -> &'static [&'static str] {
+        match self {
+            ComponentKind::Activity => &[
+                "onCreate(Landroid/os/Bundle;)V",
+                "onStart()V",
+                "onRestart()V",
+                "onResume()V",
+                "onPause()V",
+                "onStop()V",
+                "onDestroy()V",
+                "onNewIntent(Landroid/content/Intent;)V",
+                "onActivityResult(IILandroid/content/Intent;)V",
+                "onSaveInstanceState(Landroid/os/Bundle;)V",
+                "onRestoreInstanceState(Landroid/os/Bundle;)V",
+            ],
+            ComponentKind::Service => &[
+                "onCreate()V",
+                "onStartCommand(Landroid/content/Intent;II)I",
+                "onStart(Landroid/content/Intent;I)V",
+                "onBind(Landroid/content/Intent;)Landroid/os/IBinder;",
+                "onUnbind(Landroid/content/Intent;)Z",
+                "onRebind(Landroid/content/Intent;)V",
+                "onHandleIntent(Landroid/content/Intent;)V",
+                "onDestroy()V",
+            ],
+            ComponentKind::Receiver => &["onReceive(Landroid/content/Context;Landroid/content/Intent;)V"],
+            ComponentKind::Provider => &[
+                "onCreate()Z",
+                "query(Landroid/net/Uri;[Ljava/lang/String;Ljava/lang/String;[Ljava/lang/String;Ljava/lang/String;)Landroid/database/Cursor;",
+                "insert(Landroid/net/Uri;Landroid/content/ContentValues;)Landroid/net/Uri;",
+                "update(Landroid/net/Uri;Landroid/content/ContentValues;Ljava/lang/String;[Ljava/lang/String;)I",
+                "delete(Landroid/net/Uri;Ljava/lang/String;[Ljava/lang/String;)I",
+                "getType(Landroid/net/Uri;)Ljava/lang/String;",
+                "call(Ljava/lang/String;Ljava/lang/String;Landroid/os/Bundle;)Landroid/os/Bundle;",
+            ],
+        }
+    }
+}
+
+const APPLICATION_CALLBACKS: [&str; 3] = [
+    "attachBaseContext(Landroid/content/Context;)V",
+    "onCreate()V",
+    "onConfigurationChanged(Landroid/content/res/Configuration;)V",
+];
+
+/// Run by the framework when it creates any component.
+const INITIALIZERS: [&str; 2] = ["<clinit>()V", "<init>()V"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct IntentFilter {
+    pub actions: Vec<String>,
+    pub categories: Vec<String>,
+    pub schemes: Vec<String>,
+    pub hosts: Vec<String>,
+    pub mime_types: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Component {
+    pub kind: ComponentKind,
+    /// Fully qualified class name, e.g. `com.example.MainActivity`.
+    pub name: String,
+    /// Name of the `activity-alias` declaring the component. `name` is then
+    /// the class of its target activity.
+    pub alias: Option<String>,
+    pub exported: Option<bool>,
+    pub permission: Option<String>,
+    pub intent_filters: Vec<IntentFilter>,
+}
+
+impl Component {
+    /// Whether other apps can start the component. Without an explicit
+    /// `exported` attribute, components with intent filters are exported.
+    pub fn is_exported(&self) -> bool {
+        self.exported.unwrap_or(!self.intent_filters.is_empty())
+    }
+}
+
+/// Contents of the `AndroidManifest.xml` of an APK.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Manifest {
+    pub package: String,
+    pub version_code: Option<u32>,
+    pub version_name: Option<String>,
+    pub min_sdk: Option<u32>,
+    pub target_sdk: Option<u32>,
+    /// Permissions requested with `uses-permission`.
+    pub permissions: Vec<String>,
+    /// Custom `Application` subclass, fully qualified.
+    pub application: Option<String>,
+    pub components: Vec<Component>,
+}
+
+fn int_attribute(element: &Element, name: &str) -> Option<u32> {
+    match element.attribute(name)? {
+        AttributeValue::Int(i) => Some(*i as u32),
+        AttributeValue::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn bool_attribute(element: &Element, name: &str) -> Option<bool> {
+    match element.attribute(name)? {
+        AttributeValue::Bool(b) => Some(*b),
+        AttributeValue::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn intent_filter(element: &Element) -> IntentFilter {
+    let names = |tag, attribute| {
+        element
+            .children_named(tag)
+            .filter_map(|child| child.string_attribute(attribute))
+            .collect()
+    };
+    IntentFilter {
+        actions: names("action", "name"),
+        categories: names("category", "name"),
+        schemes: names("data", "scheme"),
+        hosts: names("data", "host"),
+        mime_types: names("data", "mimeType"),
+    }
+}
+
+fn component(
+    kind: ComponentKind,
+    name: String,
+    alias: Option<String>,
+    element: &Element,
+) -> Component {
+    Component {
+        kind,
+        name,
+        alias,
+        exported: bool_attribute(element, "exported"),
+        permission: element.string_attribute("permission"),
+        intent_filters: element
+            .children_named("intent-filter")
+            .map(intent_filter)
+            .collect(),
+    }
+}
+
+impl Manifest {
+    /// Parses the binary XML of a manifest.
+    pub fn from_axml(bytes: &[u8]) -> Result<Self, AxmlError> {
+        Ok(Self::from_element(&axml::parse(bytes)?))
+    }
+
+    pub fn from_element(root: &Element) -> Self {
+        let package = root.string_attribute("package").unwrap_or_default();
+        let sdk = root.children_named("uses-sdk").next();
+        let mut manifest = Manifest {
+            version_code: int_attribute(root, "versionCode"),
+            version_name: root.string_attribute("versionName"),
+            min_sdk: sdk.and_then(|sdk| int_attribute(sdk, "minSdkVersion")),
+            target_sdk: sdk.and_then(|sdk| int_attribute(sdk, "targetSdkVersion")),
+            permissions: root
+                .children
+                .iter()
+                .filter(|child| child.name.starts_with("uses-permission"))
+                .filter_map(|child| child.string_attribute("name"))
+                .collect(),
+            package,
+            ..Manifest::default()
+        };
+        if let Some(application) = root.children_named("application").next() {
+            manifest.application = application
+                .string_attribute("name")
+                .map(|name| manifest.qualified_name(&name));
+            for kind in &[
+                ComponentKind::Activity,
+                ComponentKind::Service,
+                ComponentKind::Receiver,
+                ComponentKind::Provider,
+            ] {
+                for element in application.children_named(kind.tag()) {
+                    let name = match element.string_attribute("name") {
+                        Some(name) => manifest.qualified_name(&name),
+                        None => continue,
+                    };
+                    manifest
+                        .components
+                        .push(component(*kind, name, None, element));
+                }
+            }
+            for element in application.children_named("activity-alias") {
+                let names = (
+                    element.string_attribute("name"),
+                    element.string_attribute("targetActivity"),
+                );
+                if let (Some(alias), Some(target)) = names {
+                    let alias = manifest.qualified_name(&alias);
+                    let target = manifest.qualified_name(&target);
+                    let alias = component(ComponentKind::Activity, target, Some(alias), element);
+                    manifest.components.push(alias);
+                }
+            }
+        }
+        manifest
+    }
+
+    /// Class names starting with a dot, or without any, are relative to the
+    /// package.
+    fn qualified_name(&self, name: &str) -> String {
+        if name.starts_with('.') {
+            format!("{}{}", self.package, name)
+        } else if !name.contains('.') {
+            format!("{}.{}", self.package, name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    pub fn components_of(&self, kind: ComponentKind) -> impl Iterator<Item = &Component> + '_ {
+        self.components
+            .iter()
+            .filter(move |component| component.kind == kind)
+    }
+
+    pub fn activities(&self) -> impl Iterator<Item = &Component> + '_ {
+        self.components_of(ComponentKind::Activity)
+    }
+
+    pub fn services(&self) -> impl Iterator<Item = &Component> + '_ {
+        self.components_of(ComponentKind::Service)
+    }
+
+    pub fn receivers(&self) -> impl Iterator<Item = &Component> + '_ {
+        self.components_of(ComponentKind::Receiver)
+    }
+
+    pub fn providers(&self) -> impl Iterator<Item = &Component> + '_ {
+        self.components_of(ComponentKind::Provider)
+    }
+
+    /// Type descriptors of the application class and of the components,
+    /// which the framework instantiates.
+    pub fn component_classes(&self) -> Vec<String> {
+        let mut classes: Vec<String> = self
+            .application
+            .iter()
+            .chain(self.components.iter().map(|component| &component.name))
+            .map(|name| class_descriptor(name))
+            .collect();
+        classes.sort();
+        classes.dedup();
+        classes
+    }
+
+    /// Methods of the APK that the framework calls on the declared
+    /// components, in the `Lclass;->name(params)return` form. Callbacks are
+    /// looked up through the superclasses defined in the APK.
+    pub fn entry_points(&self, hierarchy: &ClassHierarchy) -> Vec<String> {
+        let classes = self
+            .application
+            .iter()
+            .map(|name| (name, &APPLICATION_CALLBACKS[..]))
+            .chain(
+                self.components
+                    .iter()
+                    .map(|component| (&component.name, component.kind.callbacks())),
+            );
+        let mut entry_points = Vec::new();
+        for (name, callbacks) in classes {
+            let class = class_descriptor(name);
+            for member in INITIALIZERS.iter().chain(callbacks) {
+                // Static initializers are not inherited
+                let defining = if member.starts_with("<") {
+                    hierarchy
+                        .get(&class)
+                        .filter(|node| node.methods.contains(*member))
+                        .map(|_| class.as_str())
+                } else {
+                    hierarchy.resolve_method(&class, member)
+                };
+                if let Some(defining) = defining {
+                    let entry_point = format!("{}->{}", defining, member);
+                    if !entry_points.contains(&entry_point) {
+                        entry_points.push(entry_point);
+                    }
+                }
+            }
+        }
+        entry_points
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::axml::Attribute;
+    use crate::hierarchy::ClassNode;
+
+    fn element(
+        name: &str,
+        attributes: &[(&str, AttributeValue)],
+        children: Vec<Element>,
+    ) -> Element {
+        Element {
+            name: name.to_string(),
+            attributes: attributes
+                .iter()
+                .map(|(name, value)| Attribute {
+                    name: name.to_string(),
+                    resource_id: None,
+                    value: value.clone(),
+                })
+                .collect(),
+            children,
+        }
+    }
+
+    fn string(s: &str) -> AttributeValue {
+        AttributeValue::String(s.to_string())
+    }
+
+    #[test]
+    fn test_manifest() {
+        let filter = element(
+            "intent-filter",
+            &[],
+            vec![
+                element(
+                    "action",
+                    &[("name", string("android.intent.action.MAIN"))],
+                    vec![],
+                ),
+                element(
+                    "category",
+                    &[("name", string("android.intent.category.LAUNCHER"))],
+                    vec![],
+                ),
+            ],
+        );
+        let root = element(
+            "manifest",
+            &[
+                ("package", string("com.example")),
+                ("versionCode", AttributeValue::Int(3)),
+                ("versionName", string("1.2")),
+            ],
+            vec![
+                element(
+                    "uses-sdk",
+                    &[
+                        ("minSdkVersion", AttributeValue::Int(21)),
+                        ("targetSdkVersion", AttributeValue::Int(30)),
+                    ],
+                    vec![],
+                ),
+                element(
+                    "uses-permission",
+                    &[("name", string("android.permission.INTERNET"))],
+                    vec![],
+                ),
+                element(
+                    "application",
+                    &[("name", string(".App"))],
+                    vec![
+                        element(
+                            "activity",
+                            &[("name", string(".MainActivity"))],
+                            vec![filter],
+                        ),
+                        element(
+                            "activity-alias",
+                            &[
+                                ("name", string(".Launcher")),
+                                ("targetActivity", string(".MainActivity")),
+                                ("exported", AttributeValue::Bool(true)),
+                            ],
+                            vec![],
+                        ),
+                        element(
+                            "receiver",
+                            &[
+                                ("name", string("org.lib.BootReceiver")),
+                                ("exported", AttributeValue::Bool(false)),
+                            ],
+                            vec![],
+                        ),
+                    ],
+                ),
+            ],
+        );
+        let manifest = Manifest::from_element(&root);
+        assert_eq!(manifest.package, "com.example");
+        assert_eq!(manifest.version_code, Some(3));
+        assert_eq!(manifest.version_name, Some(String::from("1.2")));
+        assert_eq!(
+            (manifest.min_sdk, manifest.target_sdk),
+            (Some(21), Some(30))
+        );
+        assert_eq!(manifest.permissions, vec!["android.permission.INTERNET"]);
+        assert_eq!(manifest.application, Some(String::from("com.example.App")));
+
+        let activity = manifest.activities().next().unwrap();
+        assert_eq!(activity.name, "com.example.MainActivity");
+        assert!(activity.is_exported());
+        assert_eq!(
+            activity.intent_filters[0].actions,
+            vec!["android.intent.action.MAIN"]
+        );
+        let alias = manifest.activities().nth(1).unwrap();
+        assert_eq!(alias.name, "com.example.MainActivity");
+        assert_eq!(alias.alias.as_deref(), Some("com.example.Launcher"));
+        assert!(alias.is_exported());
+        let receiver = manifest.receivers().next().unwrap();
+        assert_eq!(receiver.name, "org.lib.BootReceiver");
+        assert!(!receiver.is_exported());
+        assert_eq!(manifest.services().count(), 0);
+    }
+
+    #[test]
+    fn test_entry_points() {
+        let manifest = Manifest {
+            package: String::from("com.example"),
+            application: Some(String::from("com.example.App")),
+            components: vec![Component {
+                kind: ComponentKind::Activity,
+                name: String::from("com.example.Main"),
+                alias: None,
+                exported: None,
+                permission: None,
+                intent_filters: Vec::new(),
+            }],
+            ..Manifest::default()
+        };
+        let mut hierarchy = ClassHierarchy::default();
+        let class = |super_class: &str, methods: &[&str]| ClassNode {
+            super_class: Some(super_class.to_string()),
+            methods: methods.iter().map(|s| s.to_string()).collect(),
+            ..ClassNode::default()
+        };
+        hierarchy.insert(
+            String::from("Lcom/example/Base;"),
+            class(
+                "Landroid/app/Activity;",
+                &["<clinit>()V", "onCreate(Landroid/os/Bundle;)V"],
+            ),
+        );
+        hierarchy.insert(
+            String::from("Lcom/example/Main;"),
+            class("Lcom/example/Base;", &["<init>()V", "onResume()V"]),
+        );
+        assert_eq!(
+            manifest.entry_points(&hierarchy),
+            vec![
+                "Lcom/example/Main;-><init>()V",
+                "Lcom/example/Base;->onCreate(Landroid/os/Bundle;)V",
+                "Lcom/example/Main;->onResume()V",
+            ]
+        );
+        assert_eq!(
+            manifest.component_classes(),
+            ["Lcom/example/App;", "Lcom/example/Main;"]
+        );
+    }
+}
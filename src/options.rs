@@ -0,0 +1,95 @@
+use crate::api::FrameworkApis;
+use crate::similarity::{Matcher, Metric};
+
+/// Minimum number of apps a package must be seen in to become a library.
+pub const DEFAULT_MIN_APPS: usize = 10;
+
+/// Options shared by `libradar` and `libradar-train`.
+pub struct Options {
+    pub min_apps: usize,
+    pub apis: FrameworkApis,
+    pub matcher: Matcher,
+    /// Skip the dex files that cannot be loaded instead of the whole input.
+    pub partial: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            min_apps: DEFAULT_MIN_APPS,
+            apis: FrameworkApis::default(),
+            matcher: Matcher::default(),
+            partial: false,
+        }
+    }
+}
+
+impl Options {
+    /// Parses `--flag value` pairs and the `--partial` switch. Flags missing
+    /// from `allowed` are rejected as unknown.
+    pub fn parse(args: &[String], allowed: &[&str]) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            if !allowed.contains(&flag.as_str()) {
+                return Err(format!("Unknown option {}", flag));
+            }
+            if flag == "--partial" {
+                options.partial = true;
+                continue;
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", flag))?;
+            match flag.as_str() {
+                "--min-apps" => {
+                    options.min_apps = value
+                        .parse()
+                        .map_err(|_| String::from("The minimum number of apps must be a number"))?
+                }
+                "--api-list" => {
+                    options.apis = FrameworkApis::from_path(value)
+                        .map_err(|e| format!("Failed to load the api list: {}", e))?
+                }
+                "--threshold" => {
+                    options.matcher.threshold = value
+                        .parse()
+                        .map_err(|_| String::from("The threshold must be a number"))?
+                }
+                "--metric" => {
+                    options.matcher.metric = Metric::from_name(value)
+                        .ok_or_else(|| format!("Unknown metric {}", value))?
+                }
+                _ => return Err(format!("Unknown option {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| String::from(*a)).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let allowed = ["--min-apps", "--threshold", "--metric", "--partial"];
+        let options = Options::parse(
+            &args(&["--min-apps", "3", "--partial", "--metric", "cosine"]),
+            &allowed,
+        )
+        .unwrap();
+        assert_eq!(options.min_apps, 3);
+        assert!(options.partial);
+        assert_eq!(options.matcher.metric, Metric::Cosine);
+        assert!(options.matcher.is_exact());
+
+        assert!(Options::parse(&args(&["--min-apps"]), &allowed).is_err());
+        assert!(Options::parse(&args(&["--threshold", "x"]), &allowed).is_err());
+        assert!(Options::parse(&args(&["--api-list", "apis.txt"]), &allowed).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use crate::apk::{Apk, DexFile};
+use crate::disass::disassemble;
+use std::collections::{BTreeMap, HashSet};
+
+/// A class of the apk together with its size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassInfo {
+    pub name: String,
+    /// Index in `Apk::dex_names` of the dex that defines the class.
+    pub dex: usize,
+    pub methods: usize,
+    pub instructions: usize,
+    pub invokes: usize,
+}
+
+/// A Java package. The counters aggregate the whole subtree, while `classes`
+/// only holds the classes declared directly in this package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageNode {
+    pub name: String,
+    pub children: BTreeMap<String, PackageNode>,
+    pub classes: Vec<ClassInfo>,
+    pub class_count: usize,
+    pub method_count: usize,
+    pub instruction_count: usize,
+    pub invoke_count: usize,
+}
+
+impl PackageNode {
+    fn new(name: String) -> Self {
+        PackageNode {
+            name,
+            children: BTreeMap::new(),
+            classes: Vec::new(),
+            class_count: 0,
+            method_count: 0,
+            instruction_count: 0,
+            invoke_count: 0,
+        }
+    }
+
+    fn add(&mut self, class: &ClassInfo) {
+        self.class_count += 1;
+        self.method_count += class.methods;
+        self.instruction_count += class.instructions;
+        self.invoke_count += class.invokes;
+    }
+
+    /// Every class of the subtree rooted at this package.
+    pub fn all_classes(&self) -> impl Iterator<Item = &ClassInfo> {
+        PreOrder { stack: vec![self] }.flat_map(|node| node.classes.iter())
+    }
+
+    pub fn pre_order(&self) -> PreOrder<'_> {
+        PreOrder { stack: vec![self] }
+    }
+
+    pub fn post_order(&self) -> PostOrder<'_> {
+        PostOrder {
+            stack: vec![(self, false)],
+        }
+    }
+}
+
+/// The packages of an apk across all its dex files. Package names are type
+/// descriptor prefixes such as `Lcom/squareup/`; the root package is `L`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageTree {
+    root: PackageNode,
+}
+
+impl Default for PackageTree {
+    fn default() -> Self {
+        PackageTree::new()
+    }
+}
+
+impl PackageTree {
+    pub fn new() -> Self {
+        PackageTree {
+            root: PackageNode::new(String::from("L")),
+        }
+    }
+
+    pub fn from_apk(apk: &Apk) -> Result<Self, Box<dyn std::error::Error>> {
+        let dex_files: Vec<(usize, &DexFile)> = apk.dex_files()?.into_iter().enumerate().collect();
+        PackageTree::from_dex_files(&dex_files)
+    }
+
+    /// Builds the tree of the classes of `dex_files`, given with their index
+    /// in `Apk::dex_names`. A class defined several times, e.g. in different
+    /// splits, is only added for its first definition.
+    pub fn from_dex_files(
+        dex_files: &[(usize, &DexFile)],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut tree = PackageTree::new();
+        let mut seen = HashSet::new();
+        for &(i, dex) in dex_files {
+            for class in dex.classes() {
+                let class = class?;
+                let name = class.jtype().type_descriptor().to_string();
+                if !seen.insert(name.clone()) {
+                    continue;
+                }
+                let mut info = ClassInfo {
+                    name,
+                    dex: i,
+                    methods: 0,
+                    instructions: 0,
+                    invokes: 0,
+                };
+                for method in class.methods() {
+                    info.methods += 1;
+                    if let Some(code) = method.code() {
+                        for ins in disassemble(code).flatten() {
+                            info.instructions += 1;
+                            if ins.is_invoke() {
+                                info.invokes += 1;
+                            }
+                        }
+                    }
+                }
+                tree.insert(info);
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Adds a class to the package named after its type descriptor, creating
+    /// the missing packages on the way.
+    pub fn insert(&mut self, class: ClassInfo) {
+        let mut node = &mut self.root;
+        node.add(&class);
+        let segments: Vec<&str> = class.name[1..].split('/').collect();
+        for segment in &segments[..segments.len() - 1] {
+            let name = format!("{}{}/", node.name, segment);
+            node = node
+                .children
+                .entry(String::from(*segment))
+                .or_insert_with(|| PackageNode::new(name));
+            node.add(&class);
+        }
+        node.classes.push(class);
+    }
+
+    pub fn root(&self) -> &PackageNode {
+        &self.root
+    }
+
+    /// Looks up a package by name, e.g. `Lcom/squareup/`. The trailing slash
+    /// is optional.
+    pub fn get(&self, package: &str) -> Option<&PackageNode> {
+        let path = package.strip_prefix('L')?;
+        let mut node = &self.root;
+        for segment in path.split('/').filter(|s| !s.is_empty()) {
+            node = node.children.get(segment)?;
+        }
+        Some(node)
+    }
+
+    pub fn pre_order(&self) -> PreOrder<'_> {
+        self.root.pre_order()
+    }
+
+    pub fn post_order(&self) -> PostOrder<'_> {
+        self.root.post_order()
+    }
+}
+
+pub struct PreOrder<'a> {
+    stack: Vec<&'a PackageNode>,
+}
+
+impl<'a> Iterator for PreOrder<'a> {
+    type Item = &'a PackageNode;
+
+    fn next(&mut self) -> Option<&'a PackageNode> {
+        let node = self.stack.pop()?;
+        self.stack.extend(node.children.values().rev());
+        Some(node)
+    }
+}
+
+pub struct PostOrder<'a> {
+    stack: Vec<(&'a PackageNode, bool)>,
+}
+
+impl<'a> Iterator for PostOrder<'a> {
+    type Item = &'a PackageNode;
+
+    fn next(&mut self) -> Option<&'a PackageNode> {
+        loop {
+            let (node, expanded) = self.stack.pop()?;
+            if expanded {
+                return Some(node);
+            }
+            self.stack.push((node, true));
+            self.stack
+                .extend(node.children.values().rev().map(|child| (child, false)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class(name: &str, methods: usize) -> ClassInfo {
+        ClassInfo {
+            name: String::from(name),
+            dex: 0,
+            methods,
+            instructions: methods * 10,
+            invokes: methods,
+        }
+    }
+
+    fn sample_tree() -> PackageTree {
+        let mut tree = PackageTree::new();
+        tree.insert(class("Lcom/squareup/okhttp/Call;", 3));
+        tree.insert(class("Lcom/squareup/okhttp/internal/Util;", 2));
+        tree.insert(class("Lcom/squareup/Picasso;", 4));
+        tree.insert(class("Lcom/example/MainActivity;", 1));
+        tree.insert(class("LDefault;", 1));
+        tree
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let tree = sample_tree();
+        assert_eq!(tree.root().class_count, 5);
+        assert_eq!(tree.root().classes.len(), 1);
+        let squareup = tree.get("Lcom/squareup/").expect("package not found");
+        assert_eq!(squareup.name, "Lcom/squareup/");
+        assert_eq!(squareup.class_count, 3);
+        assert_eq!(squareup.method_count, 9);
+        assert_eq!(squareup.instruction_count, 90);
+        assert_eq!(squareup.classes.len(), 1);
+        assert_eq!(squareup.all_classes().count(), 3);
+        assert!(tree.get("Lcom/missing/").is_none());
+        assert_eq!(tree.get("Lcom/squareup"), Some(squareup));
+    }
+
+    #[test]
+    fn test_traversal_order() {
+        let tree = sample_tree();
+        let pre: Vec<&str> = tree.pre_order().map(|n| n.name.as_str()).collect();
+        assert_eq!(
+            pre,
+            [
+                "L",
+                "Lcom/",
+                "Lcom/example/",
+                "Lcom/squareup/",
+                "Lcom/squareup/okhttp/",
+                "Lcom/squareup/okhttp/internal/"
+            ]
+        );
+        let post: Vec<&str> = tree.post_order().map(|n| n.name.as_str()).collect();
+        assert_eq!(
+            post,
+            [
+                "Lcom/example/",
+                "Lcom/squareup/okhttp/internal/",
+                "Lcom/squareup/okhttp/",
+                "Lcom/squareup/",
+                "Lcom/",
+                "L"
+            ]
+        );
+    }
+}
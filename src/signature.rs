@@ -0,0 +1,585 @@
+use positioned_io::ReadAt;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io;
+
+const EOCD_MAGIC: &[u8] = b"PK\x05\x06";
+const EOCD_SIZE: usize = 22;
+const SIGNING_BLOCK_MAGIC: &[u8] = b"APK Sig Block 42";
+
+const V2_BLOCK_ID: u32 = 0x7109_871a;
+const V3_BLOCK_ID: u32 = 0xf053_68c0;
+const V31_BLOCK_ID: u32 = 0x1b93_ad61;
+
+const DER_INTEGER: u8 = 0x02;
+const DER_OID: u8 = 0x06;
+const DER_UTF8_STRING: u8 = 0x0c;
+const DER_BMP_STRING: u8 = 0x1e;
+const DER_SEQUENCE: u8 = 0x30;
+const DER_SET: u8 = 0x31;
+const DER_CONTEXT_0: u8 = 0xa0;
+
+/// Short names of the attributes of X.509 names, by OID.
+const NAME_ATTRIBUTES: [(&str, &str); 9] = [
+    ("2.5.4.3", "CN"),
+    ("2.5.4.5", "SERIALNUMBER"),
+    ("2.5.4.6", "C"),
+    ("2.5.4.7", "L"),
+    ("2.5.4.8", "ST"),
+    ("2.5.4.9", "STREET"),
+    ("2.5.4.10", "O"),
+    ("2.5.4.11", "OU"),
+    ("1.2.840.113549.1.9.1", "EMAILADDRESS"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SignatureScheme {
+    /// JAR signature in `META-INF/`.
+    V1,
+    V2,
+    V3,
+    /// v3 signature for platform releases with key rotation support.
+    V31,
+    /// Signature in a separate `.idsig` file, for incremental installs.
+    V4,
+}
+
+impl fmt::Display for SignatureScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureScheme::V1 => write!(f, "v1"),
+            SignatureScheme::V2 => write!(f, "v2"),
+            SignatureScheme::V3 => write!(f, "v3"),
+            SignatureScheme::V31 => write!(f, "v3.1"),
+            SignatureScheme::V4 => write!(f, "v4"),
+        }
+    }
+}
+
+/// X.509 certificate of a signer. Names are rendered like `apksigner` and
+/// `keytool` do, e.g. `CN=Android Debug, O=Android, C=US`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Certificate {
+    pub subject: String,
+    pub issuer: String,
+    /// SHA-256 of the DER encoding of the certificate.
+    pub sha256: [u8; 32],
+}
+
+impl Certificate {
+    /// Lowercase hexadecimal SHA-256 fingerprint.
+    pub fn fingerprint(&self) -> String {
+        self.sha256.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// A signer of the APK. Its signing certificate comes first. Signatures are
+/// parsed but not verified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub scheme: SignatureScheme,
+    pub certificates: Vec<Certificate>,
+}
+
+#[derive(Debug)]
+pub enum SignatureError {
+    /// A field goes past the end of its enclosing structure.
+    Truncated,
+    /// Unexpected DER tag, or an encoding that is not DER.
+    InvalidDer {
+        tag: u8,
+    },
+    InvalidSigningBlock,
+    Io(io::Error),
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Truncated => write!(f, "Truncated signature"),
+            SignatureError::InvalidDer { tag } => write!(f, "Invalid DER element {:#04x}", tag),
+            SignatureError::InvalidSigningBlock => write!(f, "Invalid APK Signing Block"),
+            SignatureError::Io(e) => write!(f, "Cannot read the signature: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+impl From<io::Error> for SignatureError {
+    fn from(e: io::Error) -> Self {
+        SignatureError::Io(e)
+    }
+}
+
+/// Signature schemes present in `signatures`, sorted.
+pub fn schemes(signatures: &[Signature]) -> Vec<SignatureScheme> {
+    let mut schemes: Vec<SignatureScheme> = signatures.iter().map(|s| s.scheme).collect();
+    schemes.sort_unstable();
+    schemes.dedup();
+    schemes
+}
+
+/// Whether a zip entry holds a v1 signature block.
+pub fn is_v1_signature_entry(name: &str) -> bool {
+    match name.strip_prefix("META-INF/") {
+        Some(file) if !file.contains('/') => {
+            let upper = file.to_ascii_uppercase();
+            upper.ends_with(".RSA") || upper.ends_with(".DSA") || upper.ends_with(".EC")
+        }
+        _ => false,
+    }
+}
+
+struct Der<'a> {
+    tag: u8,
+    content: &'a [u8],
+    /// Tag, length and content.
+    raw: &'a [u8],
+}
+
+/// First DER element of `bytes`, and the bytes after it.
+fn der_element(bytes: &[u8]) -> Result<(Der<'_>, &[u8]), SignatureError> {
+    let tag = *bytes.first().ok_or(SignatureError::Truncated)?;
+    let first = *bytes.get(1).ok_or(SignatureError::Truncated)?;
+    let (len, header) = if first & 0x80 == 0 {
+        (first as usize, 2)
+    } else {
+        // Indefinite lengths are BER only
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return Err(SignatureError::InvalidDer { tag });
+        }
+        let len_bytes = bytes.get(2..2 + count).ok_or(SignatureError::Truncated)?;
+        let len = len_bytes
+            .iter()
+            .fold(0usize, |len, &b| (len << 8) | b as usize);
+        (len, 2 + count)
+    };
+    let end = header.checked_add(len).ok_or(SignatureError::Truncated)?;
+    if end > bytes.len() {
+        return Err(SignatureError::Truncated);
+    }
+    let der = Der {
+        tag,
+        content: &bytes[header..end],
+        raw: &bytes[..end],
+    };
+    Ok((der, &bytes[end..]))
+}
+
+fn expect_der(bytes: &[u8], tag: u8) -> Result<(Der<'_>, &[u8]), SignatureError> {
+    let (der, rest) = der_element(bytes)?;
+    if der.tag != tag {
+        return Err(SignatureError::InvalidDer { tag: der.tag });
+    }
+    Ok((der, rest))
+}
+
+fn oid_string(oid: &[u8]) -> String {
+    let mut arcs = Vec::new();
+    let mut value: u64 = 0;
+    for &b in oid {
+        value = (value << 7) | (b & 0x7f) as u64;
+        if b & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = (value / 40).min(2);
+                arcs.push(first);
+                arcs.push(value - first * 40);
+            } else {
+                arcs.push(value);
+            }
+            value = 0;
+        }
+    }
+    let arcs: Vec<String> = arcs.iter().map(u64::to_string).collect();
+    arcs.join(".")
+}
+
+fn name_value(der: &Der) -> String {
+    match der.tag {
+        DER_BMP_STRING => {
+            let units: Vec<u16> = der
+                .content
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        // Printable, IA5 and T61 strings are in practice ASCII
+        DER_UTF8_STRING | 0x13 | 0x14 | 0x16 => String::from_utf8_lossy(der.content).into_owned(),
+        _ => der.raw.iter().map(|b| format!("{:02x}", b)).collect(),
+    }
+}
+
+/// Renders an X.509 name, most specific attribute first.
+fn name_string(name: &[u8]) -> Result<String, SignatureError> {
+    let mut attributes = Vec::new();
+    let mut rdns = name;
+    while !rdns.is_empty() {
+        let (rdn, rest) = expect_der(rdns, DER_SET)?;
+        rdns = rest;
+        let mut pairs = rdn.content;
+        while !pairs.is_empty() {
+            let (pair, rest) = expect_der(pairs, DER_SEQUENCE)?;
+            pairs = rest;
+            let (oid, value) = expect_der(pair.content, DER_OID)?;
+            let (value, _) = der_element(value)?;
+            let oid = oid_string(oid.content);
+            let key = NAME_ATTRIBUTES
+                .iter()
+                .find(|(known, _)| *known == oid)
+                .map_or(oid.clone(), |(_, key)| key.to_string());
+            attributes.push(format!("{}={}", key, name_value(&value)));
+        }
+    }
+    attributes.reverse();
+    Ok(attributes.join(", "))
+}
+
+/// Parses a DER encoded X.509 certificate.
+pub fn parse_certificate(der: &[u8]) -> Result<Certificate, SignatureError> {
+    let (certificate, _) = expect_der(der, DER_SEQUENCE)?;
+    let (tbs, _) = expect_der(certificate.content, DER_SEQUENCE)?;
+    let mut fields = tbs.content;
+    // Explicit version, absent for v1 certificates
+    if fields.first() == Some(&DER_CONTEXT_0) {
+        fields = der_element(fields)?.1;
+    }
+    let (_serial, fields) = expect_der(fields, DER_INTEGER)?;
+    let (_algorithm, fields) = expect_der(fields, DER_SEQUENCE)?;
+    let (issuer, fields) = expect_der(fields, DER_SEQUENCE)?;
+    let (_validity, fields) = expect_der(fields, DER_SEQUENCE)?;
+    let (subject, _) = expect_der(fields, DER_SEQUENCE)?;
+    Ok(Certificate {
+        subject: name_string(subject.content)?,
+        issuer: name_string(issuer.content)?,
+        sha256: Sha256::digest(certificate.raw).into(),
+    })
+}
+
+/// Certificates of a PKCS #7 `SignedData`, as found in the v1 signature
+/// blocks `META-INF/*.RSA`, `*.DSA` and `*.EC`.
+pub fn parse_pkcs7(der: &[u8]) -> Result<Vec<Certificate>, SignatureError> {
+    let (content_info, _) = expect_der(der, DER_SEQUENCE)?;
+    let (_content_type, content) = expect_der(content_info.content, DER_OID)?;
+    let (content, _) = expect_der(content, DER_CONTEXT_0)?;
+    let (signed_data, _) = expect_der(content.content, DER_SEQUENCE)?;
+    let (_version, fields) = expect_der(signed_data.content, DER_INTEGER)?;
+    let (_digest_algorithms, fields) = expect_der(fields, DER_SET)?;
+    let (_content_info, fields) = expect_der(fields, DER_SEQUENCE)?;
+    let mut certificates = Vec::new();
+    if fields.first() == Some(&DER_CONTEXT_0) {
+        let (set, _) = der_element(fields)?;
+        let mut rest = set.content;
+        while !rest.is_empty() {
+            let (certificate, next) = der_element(rest)?;
+            rest = next;
+            certificates.push(parse_certificate(certificate.raw)?);
+        }
+    }
+    Ok(certificates)
+}
+
+/// Value prefixed by its `u32` length, and the bytes after it.
+fn length_prefixed(bytes: &[u8]) -> Result<(&[u8], &[u8]), SignatureError> {
+    let len = bytes.get(..4).ok_or(SignatureError::Truncated)?;
+    let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    let value = bytes.get(4..4 + len).ok_or(SignatureError::Truncated)?;
+    Ok((value, &bytes[4 + len..]))
+}
+
+/// Certificates of a length-prefixed sequence of length-prefixed DER
+/// certificates.
+fn certificate_list(bytes: &[u8]) -> Result<Vec<Certificate>, SignatureError> {
+    let mut certificates = Vec::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let (certificate, next) = length_prefixed(rest)?;
+        rest = next;
+        certificates.push(parse_certificate(certificate)?);
+    }
+    Ok(certificates)
+}
+
+/// Signers of a v2 or v3 block. In both, the signed data starts with the
+/// digests and then the certificates.
+fn parse_signers(value: &[u8], scheme: SignatureScheme) -> Result<Vec<Signature>, SignatureError> {
+    let (mut signers, _) = length_prefixed(value)?;
+    let mut signatures = Vec::new();
+    while !signers.is_empty() {
+        let (signer, rest) = length_prefixed(signers)?;
+        signers = rest;
+        let (signed_data, _) = length_prefixed(signer)?;
+        let (_digests, fields) = length_prefixed(signed_data)?;
+        let (certificates, _) = length_prefixed(fields)?;
+        signatures.push(Signature {
+            scheme,
+            certificates: certificate_list(certificates)?,
+        });
+    }
+    Ok(signatures)
+}
+
+/// Reads the APK Signing Block, which lies right before the zip central
+/// directory. Returns its ID-value pairs, or `None` for unsigned or v1 only
+/// APKs.
+pub fn read_signing_block<R: ReadAt>(
+    reader: &R,
+    size: u64,
+) -> Result<Option<Vec<u8>>, SignatureError> {
+    // The end of central directory record ends with a comment of up to 64KiB
+    let tail_len = size.min((EOCD_SIZE + 0xffff) as u64) as usize;
+    if tail_len < EOCD_SIZE {
+        return Err(SignatureError::InvalidSigningBlock);
+    }
+    let mut tail = vec![0; tail_len];
+    reader.read_exact_at(size - tail_len as u64, &mut tail)?;
+    let eocd = (0..=tail_len.saturating_sub(EOCD_SIZE)).rev().find(|&i| {
+        let comment_len = u16::from_le_bytes([tail[i + 20], tail[i + 21]]) as usize;
+        &tail[i..i + 4] == EOCD_MAGIC && i + EOCD_SIZE + comment_len == tail_len
+    });
+    let eocd = match eocd {
+        Some(eocd) => &tail[eocd..],
+        None => return Err(SignatureError::InvalidSigningBlock),
+    };
+    let central_directory = u32::from_le_bytes([eocd[16], eocd[17], eocd[18], eocd[19]]) as u64;
+
+    // Footer: size of the block without its first size field, then magic
+    if central_directory < 32 {
+        return Ok(None);
+    }
+    let mut footer = [0; 24];
+    reader.read_exact_at(central_directory - 24, &mut footer)?;
+    if &footer[8..] != SIGNING_BLOCK_MAGIC {
+        return Ok(None);
+    }
+    let mut block_size = [0; 8];
+    block_size.copy_from_slice(&footer[..8]);
+    let block_size = u64::from_le_bytes(block_size);
+    let block_len = match block_size.checked_add(8) {
+        Some(len) if block_size >= 24 && len <= central_directory => len,
+        _ => return Err(SignatureError::InvalidSigningBlock),
+    };
+    let mut block = vec![0; block_len as usize];
+    reader.read_exact_at(central_directory - block_len, &mut block)?;
+    if block[..8] != footer[..8] {
+        return Err(SignatureError::InvalidSigningBlock);
+    }
+    block.truncate(block.len() - 24);
+    block.drain(..8);
+    Ok(Some(block))
+}
+
+/// v2, v3 and v3.1 signatures of the ID-value pairs of an APK Signing
+/// Block. Other blocks, e.g. padding or SourceStamp, are ignored.
+pub fn parse_signing_block(pairs: &[u8]) -> Result<Vec<Signature>, SignatureError> {
+    let mut signatures = Vec::new();
+    let mut rest = pairs;
+    while !rest.is_empty() {
+        let len = rest.get(..8).ok_or(SignatureError::Truncated)?;
+        let mut len_bytes = [0; 8];
+        len_bytes.copy_from_slice(len);
+        let len = u64::from_le_bytes(len_bytes) as usize;
+        let pair = rest
+            .get(8..8usize.saturating_add(len))
+            .filter(|pair| pair.len() >= 4)
+            .ok_or(SignatureError::Truncated)?;
+        rest = &rest[8 + len..];
+        let id = u32::from_le_bytes([pair[0], pair[1], pair[2], pair[3]]);
+        let scheme = match id {
+            V2_BLOCK_ID => SignatureScheme::V2,
+            V3_BLOCK_ID => SignatureScheme::V3,
+            V31_BLOCK_ID => SignatureScheme::V31,
+            _ => continue,
+        };
+        signatures.extend(parse_signers(&pair[4..], scheme)?);
+    }
+    Ok(signatures)
+}
+
+/// Parses a v4 signature file (`<apk>.idsig`).
+pub fn parse_idsig(bytes: &[u8]) -> Result<Signature, SignatureError> {
+    let fields = bytes.get(4..).ok_or(SignatureError::Truncated)?;
+    let (_hashing_info, fields) = length_prefixed(fields)?;
+    let (signing_info, _) = length_prefixed(fields)?;
+    let (_apk_digest, fields) = length_prefixed(signing_info)?;
+    let (certificate, _) = length_prefixed(fields)?;
+    Ok(Signature {
+        scheme: SignatureScheme::V4,
+        certificates: vec![parse_certificate(certificate)?],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![tag];
+        if content.len() < 0x80 {
+            bytes.push(content.len() as u8);
+        } else {
+            bytes.push(0x82);
+            bytes.extend_from_slice(&(content.len() as u16).to_be_bytes());
+        }
+        bytes.extend_from_slice(content);
+        bytes
+    }
+
+    fn name(attributes: &[(&[u8], &str)]) -> Vec<u8> {
+        let rdns: Vec<u8> = attributes
+            .iter()
+            .flat_map(|(oid, value)| {
+                let pair = [der(DER_OID, oid), der(0x13, value.as_bytes())].concat();
+                der(DER_SET, &der(DER_SEQUENCE, &pair))
+            })
+            .collect();
+        der(DER_SEQUENCE, &rdns)
+    }
+
+    fn certificate(common_name: &str) -> Vec<u8> {
+        let algorithm = der(DER_SEQUENCE, &der(DER_OID, &[0x2a, 0x86, 0x48]));
+        let tbs = [
+            der(DER_CONTEXT_0, &der(DER_INTEGER, &[2])),
+            der(DER_INTEGER, &[1]),
+            algorithm.clone(),
+            name(&[(&[0x55, 0x04, 0x03], "Issuer")]),
+            der(DER_SEQUENCE, &[]),
+            name(&[
+                (&[0x55, 0x04, 0x06], "US"),
+                (&[0x55, 0x04, 0x0a], "Android"),
+                (&[0x55, 0x04, 0x03], common_name),
+            ]),
+        ]
+        .concat();
+        let signature = der(0x03, &[0, 1, 2]);
+        der(
+            DER_SEQUENCE,
+            &[der(DER_SEQUENCE, &tbs), algorithm, signature].concat(),
+        )
+    }
+
+    fn prefixed(value: &[u8]) -> Vec<u8> {
+        [&(value.len() as u32).to_le_bytes()[..], value].concat()
+    }
+
+    #[test]
+    fn test_certificate() {
+        let der = certificate("Android Debug");
+        let certificate = parse_certificate(&der).unwrap();
+        assert_eq!(certificate.subject, "CN=Android Debug, O=Android, C=US");
+        assert_eq!(certificate.issuer, "CN=Issuer");
+        assert_eq!(certificate.fingerprint().len(), 64);
+        assert_eq!(
+            oid_string(&[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d]),
+            "1.2.840.113549"
+        );
+        assert!(parse_certificate(&der[..der.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_pkcs7() {
+        let signed_data = [
+            der(DER_INTEGER, &[1]),
+            der(DER_SET, &[]),
+            der(DER_SEQUENCE, &der(DER_OID, &[0x2a])),
+            der(DER_CONTEXT_0, &certificate("Signer")),
+            der(DER_SET, &[]),
+        ]
+        .concat();
+        let content_info = [
+            der(DER_OID, &[0x2a]),
+            der(DER_CONTEXT_0, &der(DER_SEQUENCE, &signed_data)),
+        ]
+        .concat();
+        let certificates = parse_pkcs7(&der(DER_SEQUENCE, &content_info)).unwrap();
+        assert_eq!(certificates.len(), 1);
+        assert_eq!(certificates[0].subject, "CN=Signer, O=Android, C=US");
+        assert!(is_v1_signature_entry("META-INF/CERT.RSA"));
+        assert!(!is_v1_signature_entry("META-INF/CERT.SF"));
+        assert!(!is_v1_signature_entry("assets/META-INF/CERT.RSA"));
+    }
+
+    #[test]
+    fn test_signing_block() {
+        let signed_data = [prefixed(&[]), prefixed(&prefixed(&certificate("V2")))].concat();
+        let signer = [prefixed(&signed_data), prefixed(&[]), prefixed(&[])].concat();
+        let value = prefixed(&prefixed(&signer));
+        let mut pairs = Vec::new();
+        for id in &[V2_BLOCK_ID, 0x4242_4242, V3_BLOCK_ID] {
+            let pair = [&id.to_le_bytes()[..], &value].concat();
+            pairs.extend_from_slice(&(pair.len() as u64).to_le_bytes());
+            pairs.extend_from_slice(&pair);
+        }
+        let block_size = (pairs.len() + 24) as u64;
+        let block = [
+            &block_size.to_le_bytes()[..],
+            &pairs,
+            &block_size.to_le_bytes(),
+            SIGNING_BLOCK_MAGIC,
+        ]
+        .concat();
+
+        // Entries, signing block, empty central directory, then its end
+        let entries = b"PK\x03\x04entries".to_vec();
+        let central_directory = (entries.len() + block.len()) as u32;
+        let mut eocd = EOCD_MAGIC.to_vec();
+        eocd.extend_from_slice(&[0; 12]);
+        eocd.extend_from_slice(&central_directory.to_le_bytes());
+        eocd.extend_from_slice(&[0; 2]);
+        let apk = [entries, block, eocd].concat();
+
+        let read = read_signing_block(&apk, apk.len() as u64).unwrap().unwrap();
+        assert_eq!(read, pairs);
+        let signatures = parse_signing_block(&read).unwrap();
+        assert_eq!(
+            schemes(&signatures),
+            vec![SignatureScheme::V2, SignatureScheme::V3]
+        );
+        assert_eq!(
+            signatures[1].certificates[0].subject,
+            "CN=V2, O=Android, C=US"
+        );
+
+        // Too short to hold an end of central directory record
+        assert!(matches!(
+            read_signing_block(&apk[..10].to_vec(), 10),
+            Err(SignatureError::InvalidSigningBlock)
+        ));
+
+        // A footer size that overflows once the leading size field is added
+        let mut hostile = apk.clone();
+        let footer = central_directory as usize - 24;
+        hostile[footer..footer + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            read_signing_block(&hostile, hostile.len() as u64),
+            Err(SignatureError::InvalidSigningBlock)
+        ));
+
+        let mut unsigned = [
+            b"PK\x03\x04entries".to_vec(),
+            apk[apk.len() - 22..].to_vec(),
+        ]
+        .concat();
+        unsigned[11 + 16..11 + 20].copy_from_slice(&11u32.to_le_bytes());
+        assert_eq!(
+            read_signing_block(&unsigned, unsigned.len() as u64).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_idsig() {
+        let signing_info = [prefixed(&[0; 32]), prefixed(&certificate("V4"))].concat();
+        let idsig = [
+            &2u32.to_le_bytes()[..],
+            &prefixed(&[]),
+            &prefixed(&signing_info),
+        ]
+        .concat();
+        let signature = parse_idsig(&idsig).unwrap();
+        assert_eq!(signature.scheme, SignatureScheme::V4);
+        assert_eq!(signature.certificates[0].subject, "CN=V4, O=Android, C=US");
+    }
+}
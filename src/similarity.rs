@@ -0,0 +1,254 @@
+use crate::features::ApiVector;
+use std::collections::HashMap;
+
+/// How two API vectors are compared.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Sum of the minimum count of every API over the sum of the maximum.
+    #[default]
+    WeightedJaccard,
+    /// Cosine of the angle between the count vectors.
+    Cosine,
+}
+
+impl Metric {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "jaccard" => Some(Metric::WeightedJaccard),
+            "cosine" => Some(Metric::Cosine),
+            _ => None,
+        }
+    }
+
+    /// Similarity of two vectors, from 0 to 1. Two empty vectors are equal.
+    pub fn similarity(&self, a: &ApiVector, b: &ApiVector) -> f64 {
+        if a.is_empty() && b.is_empty() {
+            return 1.0;
+        }
+        match self {
+            Metric::WeightedJaccard => {
+                let mut min = 0;
+                let mut max = 0;
+                for (api, count) in a {
+                    min += (*count).min(b.get(api));
+                    max += (*count).max(b.get(api));
+                }
+                for (api, count) in b {
+                    if a.get(api) == 0 {
+                        max += count;
+                    }
+                }
+                min as f64 / max as f64
+            }
+            Metric::Cosine => {
+                let dot: f64 = a
+                    .iter()
+                    .map(|(api, count)| (count * b.get(api)) as f64)
+                    .sum();
+                let norm = |v: &ApiVector| -> f64 {
+                    v.iter()
+                        .map(|(_, count)| (count * count) as f64)
+                        .sum::<f64>()
+                        .sqrt()
+                };
+                let norms = norm(a) * norm(b);
+                if norms == 0.0 {
+                    0.0
+                } else {
+                    dot / norms
+                }
+            }
+        }
+    }
+
+    /// Upper bound of `similarity(a, b)` that only looks at the sizes of the
+    /// vectors, used to skip hopeless comparisons.
+    pub fn upper_bound(&self, a: &ApiVector, b: &ApiVector) -> f64 {
+        match self {
+            Metric::WeightedJaccard => {
+                let (ta, tb) = (a.total(), b.total());
+                if ta == tb {
+                    1.0
+                } else {
+                    ta.min(tb) as f64 / ta.max(tb) as f64
+                }
+            }
+            Metric::Cosine => 1.0,
+        }
+    }
+}
+
+/// A similarity metric together with the score two vectors need to be
+/// considered the same.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matcher {
+    pub metric: Metric,
+    pub threshold: f64,
+}
+
+impl Default for Matcher {
+    /// Only identical vectors match.
+    fn default() -> Self {
+        Matcher {
+            metric: Metric::default(),
+            threshold: 1.0,
+        }
+    }
+}
+
+impl Matcher {
+    pub fn new(metric: Metric, threshold: f64) -> Self {
+        Matcher { metric, threshold }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.threshold >= 1.0
+    }
+
+    /// The similarity of `a` and `b` when it reaches the threshold.
+    pub fn matches(&self, a: &ApiVector, b: &ApiVector) -> Option<f64> {
+        if a == b {
+            return Some(1.0);
+        }
+        if self.is_exact() || self.metric.upper_bound(a, b) < self.threshold {
+            return None;
+        }
+        let similarity = self.metric.similarity(a, b);
+        if similarity >= self.threshold {
+            Some(similarity)
+        } else {
+            None
+        }
+    }
+}
+
+/// Inverted index from APIs to the vectors invoking them. Vectors without a
+/// common API have a similarity of 0, so a match above a positive threshold
+/// is only searched among the vectors sharing an API with the query.
+#[derive(Debug, Default, Clone)]
+pub struct VectorIndex {
+    by_api: HashMap<String, Vec<usize>>,
+    len: usize,
+}
+
+impl VectorIndex {
+    /// Indexes `v` and returns its id, the number of vectors indexed before.
+    pub fn insert(&mut self, v: &ApiVector) -> usize {
+        let id = self.len;
+        for (api, _) in v {
+            self.by_api.entry(api.clone()).or_default().push(id);
+        }
+        self.len += 1;
+        id
+    }
+
+    /// Ids of the vectors sharing an API with `v`, in insertion order.
+    pub fn candidates(&self, v: &ApiVector) -> Vec<usize> {
+        let mut ids: Vec<usize> = v
+            .iter()
+            .filter_map(|(api, _)| self.by_api.get(api))
+            .flatten()
+            .copied()
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+}
+
+/// APIs invoked more times on one side of a comparison than on the other,
+/// with the number of extra invocations.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ApiDiff {
+    pub only_left: Vec<(String, usize)>,
+    pub only_right: Vec<(String, usize)>,
+}
+
+impl ApiDiff {
+    pub fn new(left: &ApiVector, right: &ApiVector) -> Self {
+        let mut diff = ApiDiff::default();
+        for (api, count) in left {
+            let other = right.get(api);
+            if *count > other {
+                diff.only_left.push((api.clone(), count - other));
+            }
+        }
+        for (api, count) in right {
+            let other = left.get(api);
+            if *count > other {
+                diff.only_right.push((api.clone(), count - other));
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.only_left.is_empty() && self.only_right.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(apis: &[(&str, usize)]) -> ApiVector {
+        let mut v = ApiVector::new();
+        for (api, count) in apis {
+            v.add(api, *count);
+        }
+        v
+    }
+
+    #[test]
+    fn test_weighted_jaccard() {
+        let a = vector(&[("A", 2), ("B", 1)]);
+        let b = vector(&[("A", 1), ("C", 1)]);
+        let s = Metric::WeightedJaccard.similarity(&a, &b);
+        assert!((s - 0.25).abs() < 1e-9);
+        assert!((Metric::WeightedJaccard.similarity(&a, &a) - 1.0).abs() < 1e-9);
+        assert!(Metric::WeightedJaccard.upper_bound(&a, &b) >= s);
+    }
+
+    #[test]
+    fn test_cosine() {
+        let a = vector(&[("A", 1)]);
+        let b = vector(&[("A", 1), ("B", 1)]);
+        let s = Metric::Cosine.similarity(&a, &b);
+        assert!((s - 1.0 / 2f64.sqrt()).abs() < 1e-9);
+        assert_eq!(Metric::Cosine.similarity(&a, &vector(&[("B", 3)])), 0.0);
+    }
+
+    #[test]
+    fn test_matcher() {
+        let a = vector(&[("A", 9), ("B", 1)]);
+        let b = vector(&[("A", 9)]);
+        assert_eq!(Matcher::default().matches(&a, &b), None);
+        assert_eq!(Matcher::default().matches(&a, &a), Some(1.0));
+        let fuzzy = Matcher::new(Metric::WeightedJaccard, 0.8);
+        assert_eq!(fuzzy.matches(&a, &b), Some(0.9));
+        assert_eq!(fuzzy.matches(&a, &vector(&[("A", 1)])), None);
+    }
+
+    #[test]
+    fn test_index() {
+        let mut index = VectorIndex::default();
+        assert_eq!(index.insert(&vector(&[("A", 1), ("B", 1)])), 0);
+        assert_eq!(index.insert(&vector(&[("C", 1)])), 1);
+        assert_eq!(index.insert(&vector(&[("B", 2)])), 2);
+        assert_eq!(index.candidates(&vector(&[("B", 1), ("A", 1)])), [0, 2]);
+        assert!(index.candidates(&vector(&[("D", 1)])).is_empty());
+    }
+
+    #[test]
+    fn test_diff() {
+        let a = vector(&[("A", 2), ("B", 1)]);
+        let b = vector(&[("A", 1), ("C", 1)]);
+        let diff = ApiDiff::new(&a, &b);
+        assert_eq!(
+            diff.only_left,
+            [(String::from("A"), 1), (String::from("B"), 1)]
+        );
+        assert_eq!(diff.only_right, [(String::from("C"), 1)]);
+        assert!(ApiDiff::new(&a, &a).is_empty());
+    }
+}
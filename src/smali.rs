@@ -0,0 +1,444 @@
+use crate::apk::{Apk, DexFile};
+use crate::disass::{
+    code_bytes, disassemble, format_literal, label_name, quote_string, CatchType, DecodedInst,
+    ExceptionTable, Payload, Resolver, SwitchKind,
+};
+use dex::class::Class;
+use dex::code::CodeItem;
+use dex::field::Field;
+use dex::method::Method;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+/// Resolves instruction references against the pools of a dex file.
+pub struct DexResolver<'a> {
+    dex: &'a DexFile,
+}
+
+impl<'a> DexResolver<'a> {
+    pub fn new(dex: &'a DexFile) -> Self {
+        DexResolver { dex }
+    }
+
+    fn type_name(&self, idx: u32) -> Option<String> {
+        let jtype = self.dex.get_type(idx).ok()?;
+        Some(jtype.type_descriptor().to_string())
+    }
+
+    fn proto_descriptor(&self, idx: u64) -> Option<String> {
+        let proto = self.dex.get_proto_item(idx).ok()?;
+        let params = self
+            .dex
+            .get_interfaces(proto.params_off())
+            .ok()?
+            .unwrap_or_default();
+        let params: Vec<String> = params
+            .iter()
+            .map(|param| param.type_descriptor().to_string())
+            .collect();
+        Some(format!(
+            "({}){}",
+            params.concat(),
+            self.type_name(proto.return_type())?
+        ))
+    }
+}
+
+impl Resolver for DexResolver<'_> {
+    fn string(&self, idx: u64) -> Option<String> {
+        let s = self.dex.get_string(idx as u32).ok()?;
+        Some(quote_string(&s))
+    }
+
+    fn type_descriptor(&self, idx: u64) -> Option<String> {
+        self.type_name(idx as u32)
+    }
+
+    fn field(&self, idx: u64) -> Option<String> {
+        let field = self.dex.get_field_item(idx).ok()?;
+        Some(format!(
+            "{}->{}:{}",
+            self.type_name(field.class_idx().into())?,
+            self.dex.get_string(field.name_idx()).ok()?,
+            self.type_name(field.type_idx().into())?
+        ))
+    }
+
+    fn method(&self, idx: u64) -> Option<String> {
+        let method = self.dex.get_method_item(idx).ok()?;
+        Some(format!(
+            "{}->{}{}",
+            self.type_name(method.class_idx().into())?,
+            self.dex.get_string(method.name_idx()).ok()?,
+            self.proto_descriptor(method.proto_idx().into())?
+        ))
+    }
+
+    fn proto(&self, idx: u64) -> Option<String> {
+        self.proto_descriptor(idx)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Member {
+    Class,
+    Field,
+    Method,
+}
+
+/// Renders access flags as smali keywords, e.g. `public static final`.
+fn access_flags(flags: u32, member: Member) -> String {
+    let names: [(u32, &str); 17] = [
+        (0x1, "public"),
+        (0x2, "private"),
+        (0x4, "protected"),
+        (0x8, "static"),
+        (0x10, "final"),
+        (0x20, "synchronized"),
+        (0x40, "volatile"),
+        (0x80, "transient"),
+        (0x100, "native"),
+        (0x200, "interface"),
+        (0x400, "abstract"),
+        (0x800, "strictfp"),
+        (0x1000, "synthetic"),
+        (0x2000, "annotation"),
+        (0x4000, "enum"),
+        (0x10000, "constructor"),
+        (0x20000, "declared-synchronized"),
+    ];
+    names
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .filter_map(|(bit, name)| match (member, bit) {
+            // ACC_SUPER is not printed by baksmali
+            (Member::Class, 0x20) => None,
+            (Member::Method, 0x40) => Some("bridge"),
+            (Member::Method, 0x80) => Some("varargs"),
+            _ => Some(*name),
+        })
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Labels to print before the instruction at each code-unit address.
+fn code_labels(code: &CodeItem) -> BTreeMap<usize, BTreeSet<String>> {
+    let mut labels: BTreeMap<usize, BTreeSet<String>> = BTreeMap::new();
+    for inst in disassemble(code).with_recovery().flatten() {
+        if let Some(target) = inst.branch_target().or_else(|| inst.payload_addr()) {
+            labels
+                .entry(target)
+                .or_default()
+                .insert(label_name(inst.op(), target));
+        }
+        if let DecodedInst::Switch { kind, .. } = inst.decode() {
+            for (_, target) in inst.switch_cases(code_bytes(code)).unwrap_or_default() {
+                labels
+                    .entry(target)
+                    .or_default()
+                    .insert(case_label(kind, target));
+            }
+        }
+    }
+
+    for block in ExceptionTable::from_code(code).blocks() {
+        labels
+            .entry(block.start)
+            .or_default()
+            .insert(format!(":try_start_{:x}", block.start));
+        labels
+            .entry(block.end)
+            .or_default()
+            .insert(format!(":try_end_{:x}", block.end));
+        for handler in &block.handlers {
+            let label = match handler.catch {
+                CatchType::All => format!(":catchall_{:x}", handler.addr),
+                CatchType::Type(_) => format!(":catch_{:x}", handler.addr),
+            };
+            labels.entry(handler.addr).or_default().insert(label);
+        }
+    }
+    labels
+}
+
+/// `.catch` directives to print after the end of each try block.
+fn catch_directives(tries: &ExceptionTable) -> BTreeMap<usize, Vec<String>> {
+    let mut directives: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for block in tries.blocks() {
+        let range = format!(
+            "{{:try_start_{:x} .. :try_end_{:x}}}",
+            block.start, block.end
+        );
+        for handler in &block.handlers {
+            let directive = match &handler.catch {
+                CatchType::All => format!(".catchall {} :catchall_{:x}", range, handler.addr),
+                CatchType::Type(name) => {
+                    format!(".catch {} {} :catch_{:x}", name, range, handler.addr)
+                }
+            };
+            directives.entry(block.end).or_default().push(directive);
+        }
+    }
+    directives
+}
+
+fn case_label(kind: SwitchKind, target: usize) -> String {
+    let prefix = match kind {
+        SwitchKind::Packed => "pswitch",
+        SwitchKind::Sparse => "sswitch",
+    };
+    format!(":{}_{:x}", prefix, target)
+}
+
+/// Smali directive of a payload, e.g. `.packed-switch ... .end packed-switch`.
+/// `switch` is the address of the switch instruction that uses it.
+fn payload_to_smali(payload: &Payload, switch: usize) -> String {
+    let mut out = String::new();
+    match payload {
+        Payload::PackedSwitch(table) => {
+            writeln!(
+                out,
+                ".packed-switch {}",
+                format_literal(table.first_key.into(), false)
+            )
+            .unwrap();
+            for (_, offset) in table.cases() {
+                let target = (switch as i64 + offset as i64) as usize;
+                writeln!(out, "    {}", case_label(SwitchKind::Packed, target)).unwrap();
+            }
+            out.push_str(".end packed-switch");
+        }
+        Payload::SparseSwitch(table) => {
+            out.push_str(".sparse-switch\n");
+            for (key, offset) in table.cases() {
+                let target = (switch as i64 + offset as i64) as usize;
+                writeln!(
+                    out,
+                    "    {} -> {}",
+                    format_literal(key.into(), false),
+                    case_label(SwitchKind::Sparse, target)
+                )
+                .unwrap();
+            }
+            out.push_str(".end sparse-switch");
+        }
+        Payload::FillArrayData(array) => {
+            let suffix = match array.element_width {
+                1 => "t",
+                2 => "s",
+                8 => "L",
+                _ => "",
+            };
+            writeln!(out, ".array-data {}", array.element_width).unwrap();
+            for element in array.elements() {
+                writeln!(out, "    {}{}", format_literal(element, false), suffix).unwrap();
+            }
+            out.push_str(".end array-data");
+        }
+    }
+    out
+}
+
+/// Smali body of a method, starting with its `.registers` directive.
+///
+/// Registers are printed as `vN`, like baksmali does with
+/// `--no-parameter-registers`.
+pub fn code_to_smali(code: &CodeItem, dex: &DexFile) -> String {
+    let resolver = DexResolver::new(dex);
+    let labels = code_labels(code);
+    let catches = catch_directives(&ExceptionTable::from_code(code));
+    let switches: HashMap<usize, usize> = disassemble(code)
+        .with_recovery()
+        .flatten()
+        .filter_map(|inst| Some((inst.payload_addr()?, inst.addr())))
+        .collect();
+    let mut out = String::new();
+    let print_labels = |out: &mut String, addr: usize| {
+        for label in labels.get(&addr).into_iter().flatten() {
+            writeln!(out, "    {}", label).unwrap();
+        }
+        for directive in catches.get(&addr).into_iter().flatten() {
+            writeln!(out, "    {}", directive).unwrap();
+        }
+    };
+
+    writeln!(out, "    .registers {}", code.registers_size()).unwrap();
+    let mut addr = 0;
+    for inst in disassemble(code).with_recovery() {
+        out.push('\n');
+        match inst {
+            Ok(inst) => {
+                print_labels(&mut out, inst.addr());
+                let text = match (inst.payload(), switches.get(&inst.addr())) {
+                    (Some(payload), Some(switch)) => payload_to_smali(&payload, *switch),
+                    _ => inst.format(&resolver),
+                };
+                for line in text.lines() {
+                    writeln!(out, "    {}", line).unwrap();
+                }
+                addr = inst.next_addr();
+            }
+            Err(e) => {
+                print_labels(&mut out, e.offset());
+                writeln!(out, "    # {}", e).unwrap();
+                addr = e.offset() + 1;
+            }
+        }
+    }
+    if labels.contains_key(&addr) || catches.contains_key(&addr) {
+        out.push('\n');
+        print_labels(&mut out, addr);
+    }
+    out
+}
+
+fn field_to_smali(field: &Field) -> String {
+    let flags = access_flags(field.access_flags().bits(), Member::Field);
+    let mut out = String::from(".field ");
+    if !flags.is_empty() {
+        out.push_str(&flags);
+        out.push(' ');
+    }
+    write!(out, "{}:{}", field.name(), field.jtype().type_descriptor()).unwrap();
+    out
+}
+
+/// Smali definition of a method, from `.method` to `.end method`.
+pub fn method_to_smali(method: &Method, dex: &DexFile) -> String {
+    let flags = access_flags(method.access_flags().bits(), Member::Method);
+    let params: Vec<String> = method
+        .params()
+        .iter()
+        .map(|param| param.type_descriptor().to_string())
+        .collect();
+    let mut out = String::from(".method ");
+    if !flags.is_empty() {
+        out.push_str(&flags);
+        out.push(' ');
+    }
+    writeln!(
+        out,
+        "{}({}){}",
+        method.name(),
+        params.concat(),
+        method.return_type().type_descriptor()
+    )
+    .unwrap();
+    if let Some(code) = method.code() {
+        out.push_str(&code_to_smali(code, dex));
+    }
+    out.push_str(".end method\n");
+    out
+}
+
+/// Smali source of a whole class, as baksmali would write it in its
+/// `.smali` file.
+pub fn class_to_smali(class: &Class, dex: &DexFile) -> String {
+    let resolver = DexResolver::new(dex);
+    let flags = access_flags(class.access_flags().bits(), Member::Class);
+    let mut out = String::from(".class ");
+    if !flags.is_empty() {
+        out.push_str(&flags);
+        out.push(' ');
+    }
+    writeln!(out, "{}", class.jtype().type_descriptor()).unwrap();
+    if let Some(super_class) = class.super_class().and_then(|id| resolver.type_name(id)) {
+        writeln!(out, ".super {}", super_class).unwrap();
+    }
+    if let Some(source) = class.source_file() {
+        writeln!(out, ".source {}", quote_string(source)).unwrap();
+    }
+
+    if !class.interfaces().is_empty() {
+        out.push_str("\n\n# interfaces\n");
+        for interface in class.interfaces() {
+            writeln!(out, ".implements {}", interface.type_descriptor()).unwrap();
+        }
+    }
+
+    let sections: [(&str, Vec<&Field>); 2] = [
+        ("static fields", class.static_fields().collect()),
+        ("instance fields", class.instance_fields().collect()),
+    ];
+    for (title, fields) in sections.iter() {
+        if !fields.is_empty() {
+            write!(out, "\n\n# {}\n", title).unwrap();
+            let fields: Vec<String> = fields.iter().map(|f| field_to_smali(f)).collect();
+            out.push_str(&fields.join("\n\n"));
+            out.push('\n');
+        }
+    }
+
+    let sections: [(&str, Vec<&Method>); 2] = [
+        ("direct methods", class.direct_methods().collect()),
+        ("virtual methods", class.virtual_methods().collect()),
+    ];
+    for (title, methods) in sections.iter() {
+        if !methods.is_empty() {
+            write!(out, "\n\n# {}\n", title).unwrap();
+            let methods: Vec<String> = methods.iter().map(|m| method_to_smali(m, dex)).collect();
+            out.push_str(&methods.join("\n"));
+        }
+    }
+    out
+}
+
+/// Smali source of the class named `name`, e.g. `Lcom/example/Foo;`, looked
+/// up in every dex file of `apk`.
+pub fn find_class_smali(
+    apk: &Apk,
+    name: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    for i in 0..apk.dex_count() {
+        let dex = apk.dex(i)?;
+        if let Some(class) = dex.find_class_by_name(name)? {
+            return Ok(Some(class_to_smali(&class, dex)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disass::{Handler, TryBlock};
+
+    #[test]
+    fn test_catch_directives() {
+        let tries = ExceptionTable::new(vec![TryBlock {
+            start: 0x2,
+            end: 0x8,
+            handlers: vec![
+                Handler {
+                    catch: CatchType::Type(String::from("Ljava/io/IOException;")),
+                    addr: 0x9,
+                },
+                Handler {
+                    catch: CatchType::All,
+                    addr: 0xc,
+                },
+            ],
+        }]);
+        let directives = catch_directives(&tries);
+        assert_eq!(
+            directives[&0x8],
+            [
+                ".catch Ljava/io/IOException; {:try_start_2 .. :try_end_8} :catch_9",
+                ".catchall {:try_start_2 .. :try_end_8} :catchall_c"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_access_flags() {
+        assert_eq!(access_flags(0x19, Member::Field), "public static final");
+        assert_eq!(access_flags(0x21, Member::Class), "public");
+        assert_eq!(
+            access_flags(0x1041, Member::Method),
+            "public bridge synthetic"
+        );
+        assert_eq!(access_flags(0x10001, Member::Method), "public constructor");
+        assert_eq!(access_flags(0x80, Member::Field), "transient");
+        assert_eq!(access_flags(0, Member::Method), "");
+    }
+}
@@ -1,5 +1,6 @@
 use dex::DexReader;
 use libradar::disass::disassemble;
+use libradar::smali::method_to_smali;
 
 #[test]
 fn test_disassembly() {
@@ -27,6 +28,7 @@ fn test_disassembly() {
             if let Some(code) = method.code() {
                 found = true;
                 for (idx, ins) in disassemble(code).enumerate() {
+                    let ins = ins.expect("Failed to disassemble");
                     assert_eq!(ins.mnemonic(), mnemonics[idx]);
                     assert_eq!(ins.is_invoke(), is_call[idx]);
                 }
@@ -39,3 +41,31 @@ fn test_disassembly() {
         "The test method to disassemble could not be found. Test missed!"
     );
 }
+
+#[test]
+fn test_smali_method() {
+    let data = std::fs::read("resources/classes.dex").expect("Can't read test dex file");
+    let dex = DexReader::from_vec(data).expect("Can't open test dex file");
+    let class = dex
+        .find_class_by_name("Lcom/devoteam/quickaction/QuickActionItem;")
+        .expect("Failed to load class")
+        .expect("class not found");
+    let method = class
+        .methods()
+        .find(|method| method.name() == "onCreateDrawableState")
+        .expect("The test method could not be found");
+    let smali = method_to_smali(method, &dex);
+
+    assert!(smali.starts_with(".method "));
+    assert!(smali.contains("onCreateDrawableState(I)[I\n"));
+    assert!(smali.ends_with(".end method\n"));
+    let body: Vec<&str> = smali
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('.') && !line.starts_with(':'))
+        .collect();
+    assert_eq!(body.len(), 9);
+    assert!(body[1].starts_with("invoke-super {"));
+    assert!(body[1].ends_with("->onCreateDrawableState(I)[I"));
+    assert!(body[5].starts_with("if-eqz ") && body[5].contains(":cond_"));
+}